@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS cohorts (
+    cohort_id serial PRIMARY KEY,
+    cohort_name varchar UNIQUE NOT NULL,
+    opens_at timestamp with time zone NOT NULL,
+    closes_at timestamp with time zone,
+    challenge json NOT NULL
+);
+
+-- Everyone who registered before cohorts existed gets lumped into one closed cohort
+INSERT INTO cohorts (cohort_name, opens_at, closes_at, challenge)
+SELECT 'legacy', MIN(registration_time), now(), '{}'
+FROM applicants
+HAVING COUNT(*) > 0;
+
+ALTER TABLE applicants ADD COLUMN cohort_id integer REFERENCES cohorts (cohort_id);
+UPDATE applicants SET cohort_id = (SELECT cohort_id FROM cohorts WHERE cohort_name = 'legacy');
+ALTER TABLE applicants ALTER COLUMN cohort_id SET NOT NULL;
+
+ALTER TABLE submissions ADD COLUMN cohort_id integer;
+UPDATE submissions SET cohort_id = applicants.cohort_id
+FROM applicants
+WHERE applicants.nuid = submissions.nuid;
+ALTER TABLE submissions ALTER COLUMN cohort_id SET NOT NULL;
+
+-- The same person can now apply once per cohort
+ALTER TABLE submissions DROP CONSTRAINT submissions_nuid_fkey;
+ALTER TABLE applicants DROP CONSTRAINT applicants_pkey;
+ALTER TABLE applicants ADD PRIMARY KEY (cohort_id, nuid);
+ALTER TABLE submissions
+    ADD FOREIGN KEY (cohort_id, nuid) REFERENCES applicants (cohort_id, nuid);
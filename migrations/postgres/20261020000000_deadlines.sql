@@ -0,0 +1,7 @@
+ALTER TABLE cohorts ADD COLUMN submission_deadline timestamp with time zone;
+
+-- The deadline used to live in the challenge config
+UPDATE cohorts SET submission_deadline = (challenge->>'deadline')::timestamp with time zone;
+
+-- Submissions after the deadline still get recorded, just flagged
+ALTER TABLE submissions ADD COLUMN late boolean NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- One row per grant so the reason behind every bit of extra time sticks around.
+-- An applicant's extension is the sum of their grants
+CREATE TABLE IF NOT EXISTS extensions (
+    extension_id serial PRIMARY KEY,
+    cohort_id integer NOT NULL,
+    nuid varchar NOT NULL,
+    extra_seconds bigint NOT NULL,
+    extra_attempts bigint NOT NULL,
+    reason text NOT NULL,
+    granted_at timestamp with time zone NOT NULL,
+    FOREIGN KEY (cohort_id, nuid) REFERENCES applicants (cohort_id, nuid)
+);
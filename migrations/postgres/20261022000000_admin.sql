@@ -0,0 +1,17 @@
+-- Reviewer keys are only ever stored hashed, the admin cli prints the key once
+CREATE TABLE IF NOT EXISTS api_keys (
+    key_id serial PRIMARY KEY,
+    key_name varchar UNIQUE NOT NULL,
+    key_hash varchar UNIQUE NOT NULL,
+    created_at timestamp with time zone NOT NULL,
+    revoked_at timestamp with time zone
+);
+
+-- Everything the admin cli does gets written here
+CREATE TABLE IF NOT EXISTS audit_log (
+    audit_id serial PRIMARY KEY,
+    actor varchar NOT NULL,
+    action varchar NOT NULL,
+    detail json NOT NULL,
+    performed_at timestamp with time zone NOT NULL
+);
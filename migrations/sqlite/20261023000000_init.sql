@@ -0,0 +1,59 @@
+-- SQLite gets the schema the postgres migrations add up to in one go, there's no
+-- older data to carry forward. Timestamps are stored as rfc3339 text in UTC, which
+-- sorts and compares correctly as plain strings. Json columns are text too
+CREATE TABLE IF NOT EXISTS cohorts (
+    cohort_id integer PRIMARY KEY AUTOINCREMENT,
+    cohort_name text UNIQUE NOT NULL,
+    opens_at text NOT NULL,
+    closes_at text,
+    submission_deadline text,
+    challenge text NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS applicants (
+    cohort_id integer NOT NULL REFERENCES cohorts (cohort_id),
+    nuid text NOT NULL,
+    applicant_name text NOT NULL,
+    registration_time text NOT NULL,
+    token blob UNIQUE NOT NULL,
+    challenge text NOT NULL,
+    solution text NOT NULL,
+    PRIMARY KEY (cohort_id, nuid)
+);
+
+CREATE TABLE IF NOT EXISTS submissions (
+    submission_id integer PRIMARY KEY AUTOINCREMENT,
+    cohort_id integer NOT NULL,
+    nuid text NOT NULL,
+    ok boolean NOT NULL,
+    submission_time text NOT NULL,
+    late boolean NOT NULL DEFAULT false,
+    FOREIGN KEY (cohort_id, nuid) REFERENCES applicants (cohort_id, nuid)
+);
+
+CREATE TABLE IF NOT EXISTS extensions (
+    extension_id integer PRIMARY KEY AUTOINCREMENT,
+    cohort_id integer NOT NULL,
+    nuid text NOT NULL,
+    extra_seconds integer NOT NULL,
+    extra_attempts integer NOT NULL,
+    reason text NOT NULL,
+    granted_at text NOT NULL,
+    FOREIGN KEY (cohort_id, nuid) REFERENCES applicants (cohort_id, nuid)
+);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    key_id integer PRIMARY KEY AUTOINCREMENT,
+    key_name text UNIQUE NOT NULL,
+    key_hash text UNIQUE NOT NULL,
+    created_at text NOT NULL,
+    revoked_at text
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    audit_id integer PRIMARY KEY AUTOINCREMENT,
+    actor text NOT NULL,
+    action text NOT NULL,
+    detail text NOT NULL,
+    performed_at text NOT NULL
+);
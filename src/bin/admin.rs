@@ -0,0 +1,260 @@
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use generate_tech_app::config::get_configuration;
+use generate_tech_app::model::admin::{
+    audit, audit_failure, delete_applicant, list_applicants, regenerate_challenge, reset_challenge,
+    revoke_token,
+};
+use generate_tech_app::model::keys::{create_reviewer_key, revoke_reviewer_key};
+use generate_tech_app::model::types::ApplicantSummary;
+use generate_tech_app::{db, telemetry};
+
+// Picks up the same configuration and env as the server, so run it from the repo root
+// (or wherever the configuration directory lives). Everything it does lands in audit_log
+#[derive(Parser)]
+#[command(about = "Manage applicants without dropping into psql")]
+struct Cli {
+    // Who to blame in the audit log
+    #[arg(long, env = "USER", default_value = "unknown")]
+    actor: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List everyone who registered
+    List {
+        #[arg(long)]
+        cohort: Option<String>,
+    },
+    /// Find applicants by part of their NUID or name
+    Search {
+        term: String,
+        #[arg(long)]
+        cohort: Option<String>,
+    },
+    /// Clear an applicant's submissions so they get all their attempts back
+    Reset {
+        nuid: String,
+        #[arg(long)]
+        cohort: Option<String>,
+    },
+    /// Give an applicant a brand new challenge, clearing their submissions
+    Regenerate {
+        nuid: String,
+        #[arg(long)]
+        cohort: Option<String>,
+    },
+    /// Replace an applicant's token so the old one stops working
+    RevokeToken {
+        nuid: String,
+        #[arg(long)]
+        cohort: Option<String>,
+    },
+    /// Delete an applicant along with their submissions and extensions
+    Delete {
+        nuid: String,
+        #[arg(long)]
+        cohort: Option<String>,
+        /// Required, since there's no undo
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Make a key reviewers can use on /applicant and /applicants, for when
+    /// require_reviewer_key is on
+    CreateKey { name: String },
+    /// Stop a reviewer key from working
+    RevokeKey { name: String },
+    /// Dump results as csv
+    Export {
+        #[arg(long)]
+        cohort: Option<String>,
+        /// Defaults to stdout
+        #[arg(long)]
+        output: Option<String>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let _ = dotenv::dotenv();
+
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let configuration =
+        get_configuration().map_err(|e| format!("failed to load configuration: {}", e))?;
+    telemetry::init(&configuration.logging, None, None)?;
+    let store = db::connect(&configuration.database).await.map_err(|e| {
+        format!(
+            "couldn't connect to {}: {}",
+            configuration.database.describe(),
+            e
+        )
+    })?;
+    // Same as the server does on startup, so a fresh database has the tables to work with
+    store
+        .migrate()
+        .await
+        .map_err(|e| format!("couldn't migrate the database: {}", e))?;
+    let store = store.as_ref();
+    let actor = cli.actor;
+
+    // Anything that changes something audits itself, successful or not. The read only
+    // commands get audited here
+    match cli.command {
+        Command::List { cohort } => {
+            let detail = json!({ "cohort": cohort });
+            let result = list_applicants(store, cohort.as_deref(), None).await;
+            let applicants = audit_failure(store, &actor, "list", detail.clone(), result).await?;
+            audit(store, &actor, "list", detail).await?;
+            print_applicants(&applicants);
+        }
+        Command::Search { term, cohort } => {
+            let detail = json!({ "term": term, "cohort": cohort });
+            let result = list_applicants(store, cohort.as_deref(), Some(&term)).await;
+            let applicants = audit_failure(store, &actor, "search", detail.clone(), result).await?;
+            audit(store, &actor, "search", detail).await?;
+            print_applicants(&applicants);
+        }
+        Command::Reset { nuid, cohort } => {
+            let (cohort, deleted) =
+                reset_challenge(store, &actor, &nuid, cohort.as_deref()).await?;
+            println!(
+                "Cleared {} submissions from {} in {}",
+                deleted, nuid, cohort
+            );
+        }
+        Command::Regenerate { nuid, cohort } => {
+            let (cohort, challenge) =
+                regenerate_challenge(store, &actor, &nuid, cohort.as_deref()).await?;
+            println!(
+                "Gave {} in {} a new challenge of {} strings",
+                nuid,
+                cohort,
+                challenge.len()
+            );
+        }
+        Command::RevokeToken { nuid, cohort } => {
+            let (cohort, token) = revoke_token(store, &actor, &nuid, cohort.as_deref()).await?;
+            println!("New token for {} in {}: {}", nuid, cohort, token);
+        }
+        Command::Delete { nuid, cohort, yes } => {
+            if !yes {
+                return Err("Deleting can't be undone - pass --yes if you mean it".into());
+            }
+            let cohort = delete_applicant(store, &actor, &nuid, cohort.as_deref()).await?;
+            println!("Deleted {} from {}", nuid, cohort);
+        }
+        Command::CreateKey { name } => {
+            let pepper = configuration.application.api_key_pepper.expose();
+            if pepper.is_empty() {
+                return Err("Set application.api_key_pepper before making reviewer keys".into());
+            }
+            let key = create_reviewer_key(store, &actor, &name, pepper).await?;
+            println!(
+                "Key for {} (this is the only time it's shown): {}",
+                name, key
+            );
+        }
+        Command::RevokeKey { name } => {
+            revoke_reviewer_key(store, &actor, &name).await?;
+            println!("Revoked {}", name);
+        }
+        Command::Export { cohort, output } => {
+            let detail = json!({ "cohort": cohort, "output": output });
+            let result = async {
+                let applicants = list_applicants(store, cohort.as_deref(), None).await?;
+                let out: Box<dyn Write> = match &output {
+                    Some(path) => Box::new(File::create(path)?),
+                    None => Box::new(io::stdout()),
+                };
+                write_csv(out, &applicants)?;
+                Ok::<_, Box<dyn Error>>(applicants.len())
+            }
+            .await;
+            let rows = audit_failure(store, &actor, "export", detail, result).await?;
+            audit(
+                store,
+                &actor,
+                "export",
+                json!({ "cohort": cohort, "output": output, "rows": rows }),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn print_applicants(applicants: &[ApplicantSummary]) {
+    println!(
+        "{:<12} {:<24} {:<16} {:>8} {:<6} {:<5} {:>10}",
+        "nuid", "name", "cohort", "attempts", "passed", "late", "minutes"
+    );
+    for applicant in applicants {
+        println!(
+            "{:<12} {:<24} {:<16} {:>8} {:<6} {:<5} {:>10}",
+            applicant.nuid,
+            applicant.name,
+            applicant.cohort,
+            applicant.attempts,
+            applicant.passed,
+            applicant.late,
+            applicant
+                .time_to_completion
+                .map(|duration| (duration.as_secs() / 60).to_string())
+                .unwrap_or_default()
+        );
+    }
+    println!("{} applicants", applicants.len());
+}
+
+fn write_csv(out: Box<dyn Write>, applicants: &[ApplicantSummary]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record([
+        "nuid",
+        "name",
+        "cohort",
+        "registration_time",
+        "attempts",
+        "passed",
+        "late",
+        "seconds_to_completion",
+        "extra_seconds",
+        "extra_attempts",
+    ])?;
+    for applicant in applicants {
+        writer.write_record([
+            applicant.nuid.clone(),
+            applicant.name.clone(),
+            applicant.cohort.clone(),
+            applicant.registration_time.to_rfc3339(),
+            applicant.attempts.to_string(),
+            applicant.passed.to_string(),
+            applicant.late.to_string(),
+            applicant
+                .time_to_completion
+                .map(|duration| duration.as_secs().to_string())
+                .unwrap_or_default(),
+            applicant.extra_time.as_secs().to_string(),
+            applicant.extra_attempts.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -1,64 +1,656 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
+    #[serde(default)]
+    pub cohorts: Vec<CohortSettings>,
+    #[serde(default)]
+    pub logging: LoggingSettings,
+    #[serde(default)]
+    pub tracing: TracingSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LoggingSettings {
+    pub format: LogFormat,
+    // Same syntax as RUST_LOG. RUST_LOG and --log-level both win over it
+    pub filter: String,
+    pub fields: LogFields,
+    pub pii: PiiSettings,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            filter: String::from("info"),
+            fields: LogFields::default(),
+            pii: PiiSettings::default(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    // Human readable, for running it locally
+    Pretty,
+    // One object per line, for whatever's collecting the logs in prod
+    Json,
+}
+
+// What goes on each line besides the message and level. The request id, method and
+// path are always on anything logged while handling a request
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LogFields {
+    // The module that logged it
+    pub target: bool,
+    pub file: bool,
+    pub line: bool,
+    pub thread: bool,
+    pub remote_addr: bool,
+    pub user_agent: bool,
+}
+
+impl Default for LogFields {
+    fn default() -> Self {
+        Self {
+            target: true,
+            file: false,
+            line: false,
+            thread: false,
+            remote_addr: false,
+            user_agent: false,
+        }
+    }
+}
+
+// How applicant data shows up in logs (and traces). Names are always left out, NUIDs
+// and tokens are hashed or masked and payloads are cut short - unless `unredacted` is
+// turned on, which is strictly for debugging
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PiiSettings {
+    pub nuids: NuidRedaction,
+    // Challenges, submissions and the like get cut off after this many characters
+    pub payload_limit: usize,
+    // Keys the NUID hashes so they can't be brute forced back out - there aren't many
+    // NUIDs. Without one a random key gets picked at startup, so hashes only line up
+    // within a single run
+    pub hash_key: Option<Secret<String>>,
+    // Logs NUIDs, names and whole payloads as is. check-config warns about it
+    pub unredacted: bool,
+}
+
+impl Default for PiiSettings {
+    fn default() -> Self {
+        Self {
+            nuids: NuidRedaction::Hash,
+            payload_limit: 64,
+            hash_key: None,
+            unredacted: false,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NuidRedaction {
+    // The same NUID always hashes the same, so one applicant can still be followed
+    // through the logs
+    Hash,
+    // Everything but the last three digits
+    Mask,
+}
+
+// Spans (requests, handlers and db calls) exported as OpenTelemetry traces, on top of
+// whatever gets logged. Off unless an exporter is picked
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TracingSettings {
+    pub exporter: TraceExporter,
+    // Collector's OTLP/gRPC endpoint, for the otlp exporter
+    pub endpoint: String,
+    // Where the file exporter appends to, one json batch of spans per line. Not `file`,
+    // APP_TRACING__FILE would get read as a secret file
+    pub path: PathBuf,
+    pub service_name: String,
+    // Share of traces that get kept, 0.0 to 1.0. A caller that sends a sampled
+    // traceparent always gets its trace kept
+    pub sample_ratio: f64,
+}
+
+impl Default for TracingSettings {
+    fn default() -> Self {
+        Self {
+            exporter: TraceExporter::None,
+            endpoint: String::from("http://localhost:4317"),
+            path: PathBuf::from("traces.jsonl"),
+            service_name: String::from(env!("CARGO_PKG_NAME")),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TraceExporter {
+    None,
+    Otlp,
+    // For testing without a collector
+    File,
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
+    // Address the server listens on - everything by default
+    #[serde(default = "default_bind")]
+    pub bind: IpAddr,
+    // How long in-flight requests get to finish after SIGINT/SIGTERM. Has to be under
+    // kill_timeout_secs so there's time left to close the db connections
+    #[serde(default = "default_drain_secs")]
+    pub drain_secs: u64,
+    // Everything on shutdown has to be done by then. Keep it in step with kill_timeout in
+    // fly.toml, past that fly kills us whether we're finished or not
+    #[serde(default = "default_kill_timeout_secs")]
+    pub kill_timeout_secs: u64,
+    // Leave it out to serve plain http, which is what we want behind fly's proxy
+    pub tls: Option<TlsSettings>,
+    // Sent as `Authorization: Bearer <key>` to hit anything under /admin, and /metrics
+    // (point the Prometheus scrape's authorization at it). Leave it out and those routes
+    // turn everyone away
+    pub admin_key: Option<Secret<String>>,
+    // Turns away /applicant and /applicants without a reviewer key (or the admin key).
+    // Off by default so existing clients keep working
+    #[serde(default)]
+    pub require_reviewer_key: bool,
+    // Mixed into reviewer keys before they're hashed, so a leaked api_keys table
+    // isn't enough on its own. Changing it invalidates every reviewer key
+    #[serde(default)]
+    pub api_key_pepper: Secret<String>,
+    #[serde(default)]
+    pub cors: CorsSettings,
+    #[serde(default)]
+    pub headers: SecurityHeaders,
+}
+
+// Anything that shouldn't end up in the logs - passwords, urls with credentials in
+// them, seeds, peppers. Debug and Display print [REDACTED], so get at the value with
+// expose() right where it's used. Serialize still writes the real value since cohort
+// settings get stored as json
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+impl<T: Serialize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+// Both are PEM. They're reloaded on SIGHUP, or whenever either file changes, so
+// renewing a cert doesn't need a restart
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct TlsSettings {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // Optional plain http listener that redirects everything to https
+    pub redirect_port: Option<u16>,
+}
+
+// For browser clients on another origin, like the applicant portal. With no origins
+// listed CORS stays off and browsers only get to call us from our own origin
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CorsSettings {
+    // Full origins, e.g. "https://apply.example.com" - no paths, no wildcards
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    // How long browsers can cache a preflight
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].map(String::from).to_vec(),
+            allowed_headers: ["authorization", "content-type", "x-request-id"]
+                .map(String::from)
+                .to_vec(),
+            max_age_secs: 600,
+        }
+    }
+}
+
+// Sent on every response, set one to "" to leave it out. /docs brings its own
+// content_security_policy since it loads Redoc from a cdn
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SecurityHeaders {
+    pub strict_transport_security: String,
+    pub content_type_options: String,
+    pub referrer_policy: String,
+    pub content_security_policy: String,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            strict_transport_security: String::from("max-age=63072000; includeSubDomains"),
+            content_type_options: String::from("nosniff"),
+            referrer_policy: String::from("no-referrer"),
+            content_security_policy: String::from("default-src 'none'; frame-ancestors 'none'"),
+        }
+    }
+}
+
+fn default_bind() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+fn default_drain_secs() -> u64 {
+    4
+}
+
+fn default_kill_timeout_secs() -> u64 {
+    5
 }
 
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct DatabaseSettings {
-    pub url: Option<String>,
+    // Either `postgres://...`, `sqlite:path/to/file.db` or `memory:` for a throwaway
+    // store that's gone when the server stops - the scheme picks the backend.
+    // When it's set it wins over the individual connection keys below, but the pool
+    // and timeout settings still apply
+    pub url: Option<Secret<String>>,
+    #[serde(default = "default_db_host")]
+    pub host: String,
+    #[serde(default = "default_db_port")]
+    pub port: u16,
     pub username: String,
-    pub password: String,
+    // Not in base.yaml - set it with APP_DATABASE__PASSWORD or point
+    // APP_DATABASE__PASSWORD_FILE at a docker/fly secret
+    pub password: Option<Secret<String>>,
     pub database_name: String,
+    #[serde(default)]
+    pub ssl_mode: SslMode,
+    // Root cert to check the server against, for verify-ca and verify-full
+    pub ca_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub min_connections: u32,
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    // How long a request waits for a free connection before giving up
+    #[serde(default = "default_acquire_timeout_secs")]
+    pub acquire_timeout_secs: u64,
+    // Postgres kills any statement running longer than this. Leave it out for no limit
+    pub statement_timeout_ms: Option<u64>,
+    // How hard the server tries to reach the db when it starts up
+    #[serde(default)]
+    pub retry: RetrySettings,
+}
+
+// Waits double after every failed attempt, up to max_backoff_ms, with some jitter so a
+// bunch of instances don't all hammer the db in step
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetrySettings {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    // Give up on startup after this long
+    pub deadline_secs: u64,
+    pub on_deadline: OnDeadline,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 250,
+            max_backoff_ms: 10_000,
+            deadline_secs: 60,
+            on_deadline: OnDeadline::Fail,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnDeadline {
+    // Exit, and let whatever's supervising us decide what's next
+    Fail,
+    // Start anyway and keep trying in the background. /health/ready stays 503 and
+    // anything touching the db errors until it's reachable and migrated
+    Degraded,
+}
+
+fn default_db_host() -> String {
+    String::from("localhost")
+}
+
+fn default_db_port() -> u16 {
+    5432
+}
+
+fn default_max_connections() -> u32 {
+    10
+}
+
+fn default_acquire_timeout_secs() -> u64 {
+    30
+}
+
+// Same names as postgres' sslmode
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    Allow,
+    #[default]
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl From<SslMode> for PgSslMode {
+    fn from(mode: SslMode) -> Self {
+        match mode {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Allow => PgSslMode::Allow,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require => PgSslMode::Require,
+            SslMode::VerifyCa => PgSslMode::VerifyCa,
+            SslMode::VerifyFull => PgSslMode::VerifyFull,
+        }
+    }
+}
+
+impl DatabaseSettings {
+    pub fn is_memory(&self) -> bool {
+        self.url
+            .as_ref()
+            .is_some_and(|url| url.expose().starts_with("memory:"))
+    }
+
+    pub fn is_sqlite(&self) -> bool {
+        self.url
+            .as_ref()
+            .is_some_and(|url| url.expose().starts_with("sqlite:"))
+    }
+
+    pub fn connect_options(&self) -> Result<PgConnectOptions, sqlx::Error> {
+        let options = match &self.url {
+            Some(url) => {
+                info!("Pulling db connection options from the database url");
+                PgConnectOptions::from_str(url.expose())?
+            }
+            None => {
+                info!("Pulling db connection options from config file");
+                let options = PgConnectOptions::new()
+                    .host(&self.host)
+                    .port(self.port)
+                    .username(&self.username)
+                    .database(&self.database_name)
+                    .ssl_mode(self.ssl_mode.into());
+                match &self.password {
+                    Some(password) => options.password(password.expose()),
+                    None => options,
+                }
+            }
+        };
+        let options = match &self.ca_cert {
+            Some(ca_cert) => options.ssl_root_cert(ca_cert),
+            None => options,
+        };
+        Ok(match self.statement_timeout_ms {
+            Some(ms) => options.options([("statement_timeout", ms.to_string())]),
+            None => options,
+        })
+    }
+
+    pub fn pool_options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .min_connections(self.min_connections)
+            .max_connections(self.max_connections)
+            .acquire_timeout(Duration::from_secs(self.acquire_timeout_secs))
+    }
+
+    // Where we're connecting, without anything secret in it
+    pub fn describe(&self) -> String {
+        match &self.url {
+            Some(_) if self.is_memory() => String::from("the in-memory store"),
+            Some(_) if self.is_sqlite() => String::from("SQLite (from database url)"),
+            Some(_) => String::from("Postgres (from database url)"),
+            None => format!(
+                "Postgres at {}:{}/{}",
+                self.host, self.port, self.database_name
+            ),
+        }
+    }
+}
+
+// Cohorts are synced into the db on startup, keyed by name. Dropping one from the
+// config doesn't delete it, so close it instead. Registration goes to whichever
+// cohort is open at the time, so keep the windows from overlapping
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct CohortSettings {
+    pub name: String,
+    // Registration window
+    pub opens_at: DateTime<Utc>,
+    // Leave this out to keep registration open indefinitely
+    pub closes_at: Option<DateTime<Utc>>,
+    // Submissions after this still get graded, they're just flagged as late.
+    // Leave it out for no deadline
+    pub submission_deadline: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub challenge: ChallengeSettings,
+}
+
+// This gets stored alongside the cohort, so it needs to round trip through json.
+// Leaving max_attempts out means there's no limit
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct ChallengeSettings {
+    // How many random strings get generated on top of the mandatory ones
+    pub size: usize,
+    pub max_attempts: Option<i64>,
+    // Mixed into the rng seed along with the nuid - defaults to the cohort id so
+    // someone reapplying doesn't get the same challenge twice
+    pub seed: Option<Secret<String>>,
+}
+
+impl Default for ChallengeSettings {
+    fn default() -> Self {
+        Self {
+            size: 100,
+            max_attempts: None,
+            seed: None,
+        }
+    }
+}
+
+// What the server's command line can change about where settings come from. The
+// defaults are what get_configuration has always done
+#[derive(Clone, Debug, Default)]
+pub struct ConfigOptions {
+    // Defaults to ./configuration
+    pub config_dir: Option<PathBuf>,
+    // Defaults to APP_ENVIRONMENT, then local
+    pub environment: Option<String>,
+    // Dotted keys like application.port, applied over the yaml, env vars and secret files
+    pub overrides: Vec<(String, String)>,
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine current directory");
-    let configuration_directory = base_path.join("configuration");
+    load_configuration(&ConfigOptions::default())
+}
 
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse app environment");
+pub fn load_configuration(options: &ConfigOptions) -> Result<Settings, config::ConfigError> {
+    let configuration_directory = match &options.config_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir()
+            .expect("Failed to determine current directory")
+            .join("configuration"),
+    };
+
+    let environment: Environment = match &options.environment {
+        Some(environment) => environment.clone(),
+        None => std::env::var("APP_ENVIRONMENT").unwrap_or_else(|_| "local".into()),
+    }
+    .try_into()
+    .map_err(config::ConfigError::Message)?;
 
     info!("Loading configs from {}", environment.as_str());
 
-    let environment_filename = format!("{}.yaml", environment.as_str());
+    let environment_file = configuration_directory.join(format!("{}.yaml", environment.as_str()));
+    // test.yaml is optional, everything else needs its file
+    if !environment.is_test() && !environment_file.exists() {
+        return Err(config::ConfigError::Message(format!(
+            "No config for the {} environment - expected {}",
+            environment.as_str(),
+            environment_file.display()
+        )));
+    }
+
+    let mut builder = config::Config::builder().add_source(config::File::from(
+        configuration_directory.join("base.yaml"),
+    ));
+    if environment.is_test() {
+        builder = builder.add_source(config::File::from_str(
+            TEST_DEFAULTS,
+            config::FileFormat::Yaml,
+        ));
+    }
+    builder = builder.add_source(config::File::from(environment_file).required(false));
+    // The bare DATABASE_URL in .env is there for local dev and the sqlx macros, tests
+    // shouldn't end up pointed at it. APP_DATABASE__URL still works for them
+    if !environment.is_test() {
+        builder = builder.add_source(config::Environment::default().separator("_"));
+    }
+    builder = builder.add_source(
+        config::Environment::with_prefix("APP")
+            .prefix_separator("_")
+            .separator("__"),
+    );
+    for (key, value) in secret_files(std::env::vars())? {
+        builder = builder.set_override(key, value)?;
+    }
+    for (key, value) in &options.overrides {
+        builder = builder.set_override(key.as_str(), value.as_str())?;
+    }
+
+    builder.build()?.try_deserialize::<Settings>()
+}
+
+// What the test environment starts from before configuration/test.yaml (if there is
+// one) gets layered on top - a fresh in-memory store every run
+const TEST_DEFAULTS: &str = r#"
+database:
+  url: "memory:"
+application:
+  host: "127.0.0.1"
+"#;
 
-    let settings = config::Config::builder()
-        .add_source(config::File::from(
-            configuration_directory.join("base.yaml"),
-        ))
-        .add_source(config::File::from(
-            configuration_directory.join(environment_filename),
-        ))
-        .add_source(config::Environment::default().separator("_"))
-        .build()?;
+// Any APP_..._FILE variable gets read from that file and used for the setting without
+// the _FILE, so APP_DATABASE__PASSWORD_FILE=/run/secrets/db fills in database.password.
+// These win over everything else. Files that can't be read are all listed in one error
+// so a bad deploy doesn't have to be fixed one secret at a time
+fn secret_files(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<Vec<(String, String)>, config::ConfigError> {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for (name, path) in vars {
+        let key = match name
+            .strip_prefix("APP_")
+            .and_then(|name| name.strip_suffix("_FILE"))
+        {
+            Some(key) => key.to_lowercase().replace("__", "."),
+            None => continue,
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(value) => found.push((key, value.trim_end_matches(['\r', '\n']).to_string())),
+            Err(e) => missing.push(format!("{} ({}: {})", key, path, e)),
+        }
+    }
 
-    settings.try_deserialize::<Settings>()
+    if missing.is_empty() {
+        Ok(found)
+    } else {
+        Err(config::ConfigError::Message(format!(
+            "Couldn't read secrets: {}",
+            missing.join(", ")
+        )))
+    }
 }
 
-// The possible runtime environment for our application.
+// The possible runtime environment for our application. Anything besides the built in
+// ones just needs a configuration/<name>.yaml - staging, a profile per developer, etc.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Environment {
     Local,
     Production,
     Docker,
+    Test,
+    Custom(String),
 }
 
 impl Environment {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             Environment::Local => "local",
             Environment::Production => "production",
             Environment::Docker => "docker",
+            Environment::Test => "test",
+            Environment::Custom(name) => name,
         }
     }
+
+    pub fn is_test(&self) -> bool {
+        *self == Environment::Test
+    }
 }
 
 impl TryFrom<String> for Environment {
@@ -68,9 +660,19 @@ impl TryFrom<String> for Environment {
             "docker" => Ok(Self::Docker),
             "local" => Ok(Self::Local),
             "production" => Ok(Self::Production),
+            "test" => Ok(Self::Test),
+            // It ends up in a file path, so nothing that could climb out of configuration/
+            other
+                if !other.is_empty()
+                    && other
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Ok(Self::Custom(other.to_string()))
+            }
             other => Err(format!(
-                "{} is not a supported environment. \
-Use either `local`, `docker`, or `production`.",
+                "{:?} is not a valid environment name. \
+Use letters, numbers, `-` and `_` only.",
                 other
             )),
         }
@@ -78,28 +680,229 @@ Use either `local`, `docker`, or `production`.",
 }
 
 impl Settings {
-    pub fn connection_string(&self) -> String {
-        match &self.database.url {
-            Some(url) => {
-                info!("Pulling db connection string from .env");
-                url.to_string()
+    pub fn port(&self) -> u16 {
+        self.application.port
+    }
+
+    // Everything we can catch without starting up. The server refuses to start on any
+    // Error, Warnings just get logged
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let mut error = |setting: String, message: &str| {
+            problems.push(ConfigProblem::Error {
+                setting,
+                message: message.to_string(),
+            })
+        };
+
+        if self.application.port == 0 {
+            error("application.port".into(), "must be between 1 and 65535");
+        }
+        if self.application.drain_secs >= self.application.kill_timeout_secs {
+            error(
+                "application.drain_secs".into(),
+                "has to be less than kill_timeout_secs, or there's no time left to close the db",
+            );
+        }
+        if let Some(tls) = &self.application.tls {
+            if !tls.cert.exists() {
+                error("application.tls.cert".into(), "file doesn't exist");
             }
-            None => {
-                info!("Pulling db connection string from config file");
-                format!(
-                    "postgres://{}:{}@{}:{}/{}",
-                    self.database.username,
-                    self.database.password,
-                    self.application.host,
-                    5432,
-                    self.database.database_name
-                )
+            if !tls.key.exists() {
+                error("application.tls.key".into(), "file doesn't exist");
+            }
+            if tls.redirect_port == Some(0) {
+                error(
+                    "application.tls.redirect_port".into(),
+                    "must be between 1 and 65535",
+                );
+            }
+            if tls.redirect_port == Some(self.application.port) {
+                error(
+                    "application.tls.redirect_port".into(),
+                    "can't be the same as application.port",
+                );
+            }
+        }
+        let cors = &self.application.cors;
+        for (i, origin) in cors.allowed_origins.iter().enumerate() {
+            let valid = origin.parse::<warp::http::Uri>().is_ok_and(|uri| {
+                uri.scheme().is_some()
+                    && uri.authority().is_some()
+                    && uri
+                        .path_and_query()
+                        .is_none_or(|path| path == "/" && !origin.ends_with('/'))
+            });
+            if !valid {
+                error(
+                    format!("application.cors.allowed_origins[{}]", i),
+                    "isn't an origin like https://apply.example.com",
+                );
+            }
+        }
+        for (i, method) in cors.allowed_methods.iter().enumerate() {
+            if warp::http::Method::from_bytes(method.as_bytes()).is_err() {
+                error(
+                    format!("application.cors.allowed_methods[{}]", i),
+                    "isn't an http method",
+                );
+            }
+        }
+        for (i, header) in cors.allowed_headers.iter().enumerate() {
+            if warp::http::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                error(
+                    format!("application.cors.allowed_headers[{}]", i),
+                    "isn't a valid header name",
+                );
+            }
+        }
+        let headers = &self.application.headers;
+        for (setting, value) in [
+            (
+                "strict_transport_security",
+                &headers.strict_transport_security,
+            ),
+            ("content_type_options", &headers.content_type_options),
+            ("referrer_policy", &headers.referrer_policy),
+            ("content_security_policy", &headers.content_security_policy),
+        ] {
+            if warp::http::HeaderValue::from_str(value).is_err() {
+                error(
+                    format!("application.headers.{}", setting),
+                    "isn't a valid header value",
+                );
+            }
+        }
+        let tracing = &self.tracing;
+        if !(0.0..=1.0).contains(&tracing.sample_ratio) {
+            error("tracing.sample_ratio".into(), "must be between 0.0 and 1.0");
+        }
+        if tracing.exporter == TraceExporter::Otlp
+            && tracing.endpoint.parse::<warp::http::Uri>().is_err()
+        {
+            error("tracing.endpoint".into(), "isn't a valid url");
+        }
+        let database = &self.database;
+        if database.url.is_none() {
+            if database.port == 0 {
+                error("database.port".into(), "must be between 1 and 65535");
+            }
+            if database.password.is_none() {
+                error(
+                    "database.password".into(),
+                    "isn't set - use APP_DATABASE__PASSWORD or APP_DATABASE__PASSWORD_FILE",
+                );
+            }
+        }
+        if database.retry.initial_backoff_ms == 0 {
+            error(
+                "database.retry.initial_backoff_ms".into(),
+                "must be at least 1",
+            );
+        }
+        if database.retry.max_backoff_ms < database.retry.initial_backoff_ms {
+            error(
+                "database.retry.max_backoff_ms".into(),
+                "can't be less than initial_backoff_ms",
+            );
+        }
+        if database.max_connections == 0 {
+            error("database.max_connections".into(), "must be at least 1");
+        }
+        if database.min_connections > database.max_connections {
+            error(
+                "database.min_connections".into(),
+                "can't be more than max_connections",
+            );
+        }
+        if let Some(ca_cert) = &database.ca_cert {
+            if !ca_cert.exists() {
+                error("database.ca_cert".into(), "file doesn't exist");
+            }
+        }
+
+        for (i, cohort) in self.cohorts.iter().enumerate() {
+            let setting = |key: &str| format!("cohorts[{}].{}", i, key);
+            if cohort.name.trim().is_empty() {
+                error(setting("name"), "can't be empty");
+            }
+            if self.cohorts[..i].iter().any(|c| c.name == cohort.name) {
+                error(setting("name"), "is used by more than one cohort");
+            }
+            if cohort
+                .closes_at
+                .is_some_and(|closes| closes <= cohort.opens_at)
+            {
+                error(setting("closes_at"), "must be after opens_at");
+            }
+            if let Some(deadline) = cohort.submission_deadline {
+                if deadline < cohort.closes_at.unwrap_or(cohort.opens_at) {
+                    error(
+                        setting("submission_deadline"),
+                        "can't be before registration closes",
+                    );
+                }
             }
+            if cohort.challenge.max_attempts.is_some_and(|max| max < 1) {
+                error(setting("challenge.max_attempts"), "must be at least 1");
+            }
+            if !crate::model::engine::challenge_has_answers(&cohort.challenge) {
+                error(setting("challenge"), "doesn't generate anything to answer");
+            }
+        }
+
+        if self.application.admin_key.is_none() {
+            problems.push(ConfigProblem::Warning {
+                setting: "application.admin_key".into(),
+                message: "isn't set, so the admin routes turn everyone away".into(),
+            });
+        }
+        if self.application.require_reviewer_key
+            && self.application.api_key_pepper.expose().is_empty()
+        {
+            problems.push(ConfigProblem::Error {
+                setting: "application.api_key_pepper".into(),
+                message: "has to be set when require_reviewer_key is on".into(),
+            });
+        }
+        if self.cohorts.is_empty() {
+            problems.push(ConfigProblem::Warning {
+                setting: "cohorts".into(),
+                message: "is empty, so nobody can register".into(),
+            });
+        }
+        if self.logging.pii.unredacted {
+            problems.push(ConfigProblem::Warning {
+                setting: "logging.pii.unredacted".into(),
+                message: "is on, so NUIDs, names and whole payloads end up in the logs".into(),
+            });
         }
+        problems
     }
+}
 
-    pub fn port(&self) -> u16 {
-        self.application.port
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigProblem {
+    Error { setting: String, message: String },
+    Warning { setting: String, message: String },
+}
+
+impl ConfigProblem {
+    pub fn is_error(&self) -> bool {
+        matches!(self, ConfigProblem::Error { .. })
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigProblem::Error { setting, message } => {
+                write!(f, "error: {} {}", setting, message)
+            }
+            ConfigProblem::Warning { setting, message } => {
+                write!(f, "warning: {} {}", setting, message)
+            }
+        }
     }
 }
 
@@ -121,10 +924,159 @@ fn test_config() {
             .unwrap();
 
         let config: Settings = config.try_deserialize().unwrap();
+        assert!(!format!("{:?}", config).contains("databaseurl"));
 
         assert_eq!(
-            config.database.url.unwrap(),
+            config.database.url.unwrap().expose().clone(),
             String::from("database@databaseurl")
         );
     })
 }
+
+#[test]
+fn test_secret_files() {
+    let path = std::env::temp_dir().join("generate-tech-app-test-secret");
+    std::fs::write(&path, "hunter2\n").unwrap();
+    let path = path.to_string_lossy().to_string();
+
+    let found = secret_files(
+        vec![
+            (String::from("APP_DATABASE__PASSWORD_FILE"), path.clone()),
+            (String::from("DATABASE_URL"), String::from("ignored")),
+        ]
+        .into_iter(),
+    )
+    .unwrap();
+    assert_eq!(
+        found,
+        vec![(String::from("database.password"), String::from("hunter2"))]
+    );
+
+    let err = secret_files(
+        vec![
+            (String::from("APP_DATABASE__PASSWORD_FILE"), path),
+            (
+                String::from("APP_APPLICATION__ADMIN_KEY_FILE"),
+                String::from("/does/not/exist"),
+            ),
+        ]
+        .into_iter(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("application.admin_key"));
+}
+
+#[test]
+fn test_environment_names() {
+    let env = |name: &str| Environment::try_from(name.to_string());
+    assert_eq!(env("Test"), Ok(Environment::Test));
+    assert_eq!(
+        env("staging"),
+        Ok(Environment::Custom(String::from("staging")))
+    );
+    assert!(env("../production").is_err());
+    assert!(env("").is_err());
+}
+
+#[test]
+fn test_validate() {
+    let config = |cohorts: &str| -> Settings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                &format!(
+                    "application:\n  port: 8080\n  host: \"127.0.0.1\"\n\
+                     database:\n  url: \"memory:\"\n  username: \"\"\n  database_name: \"\"\n\
+                     cohorts:\n{}",
+                    cohorts
+                ),
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    };
+
+    let ok = config("  - name: \"fall\"\n    opens_at: \"2026-01-01T00:00:00Z\"\n");
+    assert!(!ok.validate().iter().any(ConfigProblem::is_error));
+
+    let backwards = config(
+        "  - name: \"fall\"\n    opens_at: \"2026-01-01T00:00:00Z\"\n    \
+         closes_at: \"2025-01-01T00:00:00Z\"\n",
+    );
+    assert!(backwards.validate().contains(&ConfigProblem::Error {
+        setting: String::from("cohorts[0].closes_at"),
+        message: String::from("must be after opens_at"),
+    }));
+
+    let pepper = ConfigProblem::Error {
+        setting: String::from("application.api_key_pepper"),
+        message: String::from("has to be set when require_reviewer_key is on"),
+    };
+    let mut keys = ok.clone();
+    keys.application.require_reviewer_key = true;
+    assert!(keys.validate().contains(&pepper));
+    keys.application.api_key_pepper = Secret::new(String::from("pepper"));
+    assert!(!keys.validate().contains(&pepper));
+
+    let drain = ConfigProblem::Error {
+        setting: String::from("application.drain_secs"),
+        message: String::from(
+            "has to be less than kill_timeout_secs, or there's no time left to close the db",
+        ),
+    };
+    assert!(!ok.validate().contains(&drain));
+    let mut slow = ok.clone();
+    slow.application.drain_secs = 5;
+    assert!(slow.validate().contains(&drain));
+    slow.application.kill_timeout_secs = 10;
+    assert!(!slow.validate().contains(&drain));
+}
+
+#[test]
+fn test_connect_options() {
+    // PgConnectOptions only has a getter for the database name, the rest is checked
+    // through its Debug output
+    let database = |keys: &str| -> DatabaseSettings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                &format!(
+                    "host: \"db.internal\"\nusername: \"app\"\ndatabase_name: \"applications\"\n{}",
+                    keys
+                ),
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    };
+
+    let options = format!("{:?}", database("").connect_options().unwrap());
+    assert!(options.contains("host: \"db.internal\""));
+    assert!(options.contains("ssl_mode: Prefer"));
+    assert!(options.contains("options: None"));
+
+    let options = database("ssl_mode: \"verify-full\"\nstatement_timeout_ms: 5000\n")
+        .connect_options()
+        .unwrap();
+    assert_eq!(options.get_database(), Some("applications"));
+    let options = format!("{:?}", options);
+    assert!(options.contains("ssl_mode: VerifyFull"));
+    assert!(options.contains("-c statement_timeout=5000"));
+
+    // The url wins over the connection keys, but the timeout still gets applied
+    let options = database(
+        "url: \"postgres://other@db.example.com:6543/fromurl?sslmode=require\"\n\
+         ssl_mode: \"disable\"\nstatement_timeout_ms: 100\n",
+    )
+    .connect_options()
+    .unwrap();
+    assert_eq!(options.get_database(), Some("fromurl"));
+    let options = format!("{:?}", options);
+    assert!(options.contains("host: \"db.example.com\""));
+    assert!(options.contains("port: 6543"));
+    assert!(options.contains("username: \"other\""));
+    assert!(options.contains("ssl_mode: Require"));
+    assert!(options.contains("-c statement_timeout=100"));
+}
@@ -0,0 +1,592 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use super::store::{PoolStats, Store, StoreError};
+use super::transactions::{
+    ApplicantLookup, ApplicantRecord, ApplicantSummary, AuditEntry, ChallengeBuilder, GradeOutcome,
+    Grader, GradingContext, RegistrationWindow, SolutionRecord,
+};
+use crate::config::{ChallengeSettings, CohortSettings};
+
+// Keeps everything in a few vecs behind a mutex. It's meant for tests and trying
+// things out, so nothing is indexed and everything is gone when the process exits.
+// It should behave the same as the queries in `transactions.rs` - if one of those
+// changes, this needs to follow
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+    cohorts: Vec<Cohort>,
+    applicants: Vec<Applicant>,
+    submissions: Vec<Submission>,
+    extensions: Vec<Extension>,
+    api_keys: Vec<ApiKey>,
+    audit_log: Vec<AuditEntry>,
+}
+
+struct Cohort {
+    cohort_id: i32,
+    cohort_name: String,
+    opens_at: DateTime<Utc>,
+    closes_at: Option<DateTime<Utc>>,
+    submission_deadline: Option<DateTime<Utc>>,
+    challenge: ChallengeSettings,
+}
+
+struct Applicant {
+    cohort_id: i32,
+    nuid: String,
+    applicant_name: String,
+    registration_time: DateTime<Utc>,
+    token: Uuid,
+    challenge: Vec<String>,
+    solution: Vec<String>,
+}
+
+struct Submission {
+    cohort_id: i32,
+    nuid: String,
+    ok: bool,
+    submission_time: DateTime<Utc>,
+    late: bool,
+}
+
+struct Extension {
+    cohort_id: i32,
+    nuid: String,
+    extra_seconds: i64,
+    extra_attempts: i64,
+}
+
+struct ApiKey {
+    key_name: String,
+    key_hash: String,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A panic while the lock is held is already a failed test, so don't bother
+    // recovering from poisoning
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // There's no querying audit_log through the store, so tests look at it here
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.tables().audit_log.clone()
+    }
+}
+
+impl Tables {
+    fn cohort(&self, cohort_id: i32) -> &Cohort {
+        self.cohorts
+            .iter()
+            .find(|cohort| cohort.cohort_id == cohort_id)
+            .expect("applicant points at a cohort that doesn't exist")
+    }
+
+    fn by_token(&self, token: Uuid) -> Result<&Applicant, StoreError> {
+        self.applicants
+            .iter()
+            .find(|applicant| applicant.token == token)
+            .ok_or(StoreError::NotFound)
+    }
+
+    // Most recent registration for this nuid, optionally in a named cohort
+    fn latest(&self, nuid: &str, cohort: Option<&str>) -> Result<&Applicant, StoreError> {
+        self.applicants
+            .iter()
+            .filter(|applicant| {
+                applicant.nuid == nuid
+                    && cohort
+                        .is_none_or(|name| self.cohort(applicant.cohort_id).cohort_name == name)
+            })
+            .max_by_key(|applicant| applicant.registration_time)
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn submissions(&self, cohort_id: i32, nuid: &str) -> impl Iterator<Item = &Submission> {
+        let nuid = nuid.to_owned();
+        self.submissions
+            .iter()
+            .filter(move |sub| sub.cohort_id == cohort_id && sub.nuid == nuid)
+    }
+
+    fn extension(&self, cohort_id: i32, nuid: &str) -> (i64, i64) {
+        self.extensions
+            .iter()
+            .filter(|ext| ext.cohort_id == cohort_id && ext.nuid == nuid)
+            .fold((0, 0), |(secs, attempts), ext| {
+                (secs + ext.extra_seconds, attempts + ext.extra_attempts)
+            })
+    }
+
+    fn remove(&mut self, cohort_id: i32, nuid: &str) {
+        self.extensions
+            .retain(|ext| !(ext.cohort_id == cohort_id && ext.nuid == nuid));
+        self.submissions
+            .retain(|sub| !(sub.cohort_id == cohort_id && sub.nuid == nuid));
+        self.applicants
+            .retain(|applicant| !(applicant.cohort_id == cohort_id && applicant.nuid == nuid));
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    // Nothing to migrate, the tables are just structs
+    async fn migrate(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn close(&self) {}
+
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    async fn migration_version(&self) -> Result<Option<i64>, StoreError> {
+        Ok(None)
+    }
+
+    async fn sync_cohort(&self, cohort: &CohortSettings) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        let cohort_id = match tables
+            .cohorts
+            .iter()
+            .position(|existing| existing.cohort_name == cohort.name)
+        {
+            Some(i) => {
+                let id = tables.cohorts[i].cohort_id;
+                tables.cohorts.remove(i);
+                id
+            }
+            None => tables.cohorts.len() as i32 + 1,
+        };
+        tables.cohorts.push(Cohort {
+            cohort_id,
+            cohort_name: cohort.name.clone(),
+            opens_at: cohort.opens_at,
+            closes_at: cohort.closes_at,
+            submission_deadline: cohort.submission_deadline,
+            challenge: cohort.challenge.clone(),
+        });
+        tables.cohorts.sort_by_key(|cohort| cohort.cohort_id);
+        Ok(())
+    }
+
+    async fn registration_window(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<RegistrationWindow, StoreError> {
+        let tables = self.tables();
+        let next_opens_at = tables
+            .cohorts
+            .iter()
+            .map(|cohort| cohort.opens_at)
+            .filter(|opens_at| *opens_at > at)
+            .min();
+        let last_closes_at = tables
+            .cohorts
+            .iter()
+            .filter_map(|cohort| cohort.closes_at)
+            .filter(|closes_at| *closes_at <= at)
+            .max();
+        Ok(RegistrationWindow {
+            next_opens_at,
+            last_closes_at,
+        })
+    }
+
+    // Holding the mutex the whole way through does the job of the row locks
+    async fn register_user(
+        &self,
+        at: DateTime<Utc>,
+        token: Uuid,
+        name: String,
+        nuid: String,
+        build: ChallengeBuilder<'_>,
+    ) -> Result<Option<Vec<String>>, StoreError> {
+        let mut tables = self.tables();
+        let (cohort_id, settings) = match tables
+            .cohorts
+            .iter()
+            .filter(|cohort| {
+                cohort.opens_at <= at && cohort.closes_at.is_none_or(|closes| closes > at)
+            })
+            .max_by_key(|cohort| cohort.opens_at)
+        {
+            Some(cohort) => (cohort.cohort_id, cohort.challenge.clone()),
+            None => return Ok(None),
+        };
+        if tables.applicants.iter().any(|applicant| {
+            (applicant.cohort_id == cohort_id && applicant.nuid == nuid) || applicant.token == token
+        }) {
+            return Err(StoreError::Duplicate);
+        }
+
+        let (challenge, solution) = build(cohort_id, &settings);
+        tables.applicants.push(Applicant {
+            cohort_id,
+            nuid,
+            applicant_name: name,
+            registration_time: at,
+            token,
+            challenge: challenge.clone(),
+            solution,
+        });
+        Ok(Some(challenge))
+    }
+
+    async fn grade_submission(
+        &self,
+        token: Uuid,
+        submission_time: DateTime<Utc>,
+        grade: Grader<'_>,
+    ) -> Result<GradeOutcome, StoreError> {
+        let mut tables = self.tables();
+        let applicant = tables.by_token(token)?;
+        let (cohort_id, nuid) = (applicant.cohort_id, applicant.nuid.clone());
+        let cohort = tables.cohort(cohort_id);
+        let graded = grade(&GradingContext {
+            solution: applicant.solution.clone(),
+            challenge: cohort.challenge.clone(),
+            deadline: cohort.submission_deadline,
+            extension: tables.extension(cohort_id, &nuid),
+            attempts: tables.submissions(cohort_id, &nuid).count() as i64,
+        });
+
+        if let GradeOutcome::Graded { ok, late } = graded {
+            tables.submissions.push(Submission {
+                cohort_id,
+                nuid,
+                ok,
+                submission_time,
+                late,
+            });
+        }
+        Ok(graded)
+    }
+
+    async fn get_applicants(
+        &self,
+        lookup: ApplicantLookup<'_>,
+        cohort: Option<&str>,
+    ) -> Result<Vec<ApplicantRecord>, StoreError> {
+        let tables = self.tables();
+        let mut records: Vec<((DateTime<Utc>, i32), ApplicantRecord)> = tables
+            .applicants
+            .iter()
+            .filter(|applicant| match lookup {
+                ApplicantLookup::Nuids(nuids) => nuids.contains(&applicant.nuid),
+                ApplicantLookup::Token(token) => applicant.token == token,
+            })
+            .filter_map(|applicant| {
+                let cohort_row = tables.cohort(applicant.cohort_id);
+                if cohort.is_some_and(|name| cohort_row.cohort_name != name) {
+                    return None;
+                }
+                let latest = tables
+                    .submissions(applicant.cohort_id, &applicant.nuid)
+                    .max_by_key(|sub| sub.submission_time);
+                let (extra_seconds, extra_attempts) =
+                    tables.extension(applicant.cohort_id, &applicant.nuid);
+                Some((
+                    (cohort_row.opens_at, applicant.cohort_id),
+                    ApplicantRecord {
+                        nuid: applicant.nuid.clone(),
+                        applicant_name: applicant.applicant_name.clone(),
+                        cohort_name: cohort_row.cohort_name.clone(),
+                        registration_time: applicant.registration_time,
+                        submission_deadline: cohort_row.submission_deadline,
+                        max_attempts: cohort_row.challenge.max_attempts,
+                        submission_time: latest.map(|sub| sub.submission_time),
+                        ok: latest.map(|sub| sub.ok),
+                        late: latest.map(|sub| sub.late),
+                        attempts: tables
+                            .submissions(applicant.cohort_id, &applicant.nuid)
+                            .count() as i64,
+                        first_success: tables
+                            .submissions(applicant.cohort_id, &applicant.nuid)
+                            .filter(|sub| sub.ok)
+                            .map(|sub| sub.submission_time)
+                            .min(),
+                        extra_seconds,
+                        extra_attempts,
+                    },
+                ))
+            })
+            .collect();
+        records
+            .sort_by(|(a_key, a), (b_key, b)| a_key.cmp(b_key).then_with(|| a.nuid.cmp(&b.nuid)));
+        Ok(records.into_iter().map(|(_, record)| record).collect())
+    }
+
+    async fn grant_extension(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+        extra_seconds: i64,
+        extra_attempts: i64,
+        _reason: &str,
+        _granted_at: DateTime<Utc>,
+    ) -> Result<(String, i64, i64, Option<DateTime<Utc>>), StoreError> {
+        let mut tables = self.tables();
+        let cohort_id = tables.latest(nuid, cohort)?.cohort_id;
+        tables.extensions.push(Extension {
+            cohort_id,
+            nuid: nuid.to_string(),
+            extra_seconds,
+            extra_attempts,
+        });
+
+        let (extra_seconds, extra_attempts) = tables.extension(cohort_id, nuid);
+        let cohort = tables.cohort(cohort_id);
+        let cohort_name = cohort.cohort_name.clone();
+        let deadline = cohort
+            .submission_deadline
+            .map(|deadline| deadline + chrono::Duration::seconds(extra_seconds));
+        for sub in tables
+            .submissions
+            .iter_mut()
+            .filter(|sub| sub.cohort_id == cohort_id && sub.nuid == nuid)
+        {
+            sub.late = deadline.is_some_and(|deadline| sub.submission_time > deadline);
+        }
+
+        Ok((cohort_name, extra_seconds, extra_attempts, deadline))
+    }
+
+    async fn retreive_token(&self, nuid: &str) -> Result<Uuid, StoreError> {
+        Ok(self.tables().latest(nuid, None)?.token)
+    }
+
+    async fn retreive_challenge(&self, token: Uuid) -> Result<Vec<String>, StoreError> {
+        Ok(self.tables().by_token(token)?.challenge.clone())
+    }
+
+    async fn retreive_soln(&self, token: Uuid) -> Result<SolutionRecord, StoreError> {
+        let tables = self.tables();
+        let applicant = tables.by_token(token)?;
+        let cohort = tables.cohort(applicant.cohort_id);
+        Ok(SolutionRecord {
+            solution: applicant.solution.clone(),
+            cohort_id: applicant.cohort_id,
+            nuid: applicant.nuid.clone(),
+            challenge: cohort.challenge.clone(),
+            submission_deadline: cohort.submission_deadline,
+        })
+    }
+
+    async fn find_applicant(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+    ) -> Result<(i32, String, ChallengeSettings), StoreError> {
+        let tables = self.tables();
+        let applicant = tables.latest(nuid, cohort)?;
+        let cohort = tables.cohort(applicant.cohort_id);
+        Ok((
+            cohort.cohort_id,
+            cohort.cohort_name.clone(),
+            cohort.challenge.clone(),
+        ))
+    }
+
+    async fn list_applicants(
+        &self,
+        cohort: Option<&str>,
+        search: Option<&str>,
+    ) -> Result<Vec<ApplicantSummary>, StoreError> {
+        let tables = self.tables();
+        let search = search.map(|term| term.to_lowercase());
+        let mut summaries: Vec<(i32, ApplicantSummary)> = tables
+            .applicants
+            .iter()
+            .filter(|applicant| {
+                search.as_ref().is_none_or(|term| {
+                    applicant.nuid.to_lowercase().contains(term)
+                        || applicant.applicant_name.to_lowercase().contains(term)
+                })
+            })
+            .filter_map(|applicant| {
+                let cohort_row = tables.cohort(applicant.cohort_id);
+                if cohort.is_some_and(|name| cohort_row.cohort_name != name) {
+                    return None;
+                }
+                let (extra_seconds, extra_attempts) =
+                    tables.extension(applicant.cohort_id, &applicant.nuid);
+                Some((
+                    applicant.cohort_id,
+                    ApplicantSummary {
+                        nuid: applicant.nuid.clone(),
+                        applicant_name: applicant.applicant_name.clone(),
+                        cohort_name: cohort_row.cohort_name.clone(),
+                        registration_time: applicant.registration_time,
+                        attempts: tables
+                            .submissions(applicant.cohort_id, &applicant.nuid)
+                            .count() as i64,
+                        first_success: tables
+                            .submissions(applicant.cohort_id, &applicant.nuid)
+                            .filter(|sub| sub.ok)
+                            .map(|sub| sub.submission_time)
+                            .min(),
+                        late: tables
+                            .submissions(applicant.cohort_id, &applicant.nuid)
+                            .max_by_key(|sub| sub.submission_time)
+                            .is_some_and(|sub| sub.late),
+                        extra_seconds,
+                        extra_attempts,
+                    },
+                ))
+            })
+            .collect();
+        summaries.sort_by(|(a_id, a), (b_id, b)| a_id.cmp(b_id).then_with(|| a.nuid.cmp(&b.nuid)));
+        Ok(summaries.into_iter().map(|(_, summary)| summary).collect())
+    }
+
+    async fn reset_submissions(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError> {
+        let mut tables = self.tables();
+        let before = tables.submissions.len();
+        tables
+            .submissions
+            .retain(|sub| !(sub.cohort_id == cohort_id && sub.nuid == nuid));
+        tables.audit_log.push(audit.clone());
+        Ok((before - tables.submissions.len()) as u64)
+    }
+
+    async fn replace_challenge(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        challenge: &[String],
+        solution: &[String],
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        tables
+            .submissions
+            .retain(|sub| !(sub.cohort_id == cohort_id && sub.nuid == nuid));
+        if let Some(applicant) = tables
+            .applicants
+            .iter_mut()
+            .find(|applicant| applicant.cohort_id == cohort_id && applicant.nuid == nuid)
+        {
+            applicant.challenge = challenge.to_vec();
+            applicant.solution = solution.to_vec();
+        }
+        tables.audit_log.push(audit.clone());
+        Ok(())
+    }
+
+    async fn replace_token(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        token: Uuid,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        if let Some(applicant) = tables
+            .applicants
+            .iter_mut()
+            .find(|applicant| applicant.cohort_id == cohort_id && applicant.nuid == nuid)
+        {
+            applicant.token = token;
+        }
+        tables.audit_log.push(audit.clone());
+        Ok(())
+    }
+
+    async fn delete_applicant(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        tables.remove(cohort_id, nuid);
+        tables.audit_log.push(audit.clone());
+        Ok(())
+    }
+
+    async fn create_api_key(
+        &self,
+        name: &str,
+        key_hash: &str,
+        _created_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        if tables
+            .api_keys
+            .iter()
+            .any(|key| key.key_name == name || key.key_hash == key_hash)
+        {
+            return Err(StoreError::Duplicate);
+        }
+        tables.api_keys.push(ApiKey {
+            key_name: name.to_string(),
+            key_hash: key_hash.to_string(),
+            revoked_at: None,
+        });
+        tables.audit_log.push(audit.clone());
+        Ok(())
+    }
+
+    async fn revoke_api_key(
+        &self,
+        name: &str,
+        revoked_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError> {
+        let mut tables = self.tables();
+        let mut revoked = 0;
+        for key in tables
+            .api_keys
+            .iter_mut()
+            .filter(|key| key.key_name == name && key.revoked_at.is_none())
+        {
+            key.revoked_at = Some(revoked_at);
+            revoked += 1;
+        }
+        if revoked > 0 {
+            tables.audit_log.push(audit.clone());
+        }
+        Ok(revoked)
+    }
+
+    async fn api_key_exists(&self, key_hash: &str) -> Result<bool, StoreError> {
+        Ok(self
+            .tables()
+            .api_keys
+            .iter()
+            .any(|key| key.key_hash == key_hash && key.revoked_at.is_none()))
+    }
+
+    async fn write_audit_log(&self, audit: &AuditEntry) -> Result<(), StoreError> {
+        self.tables().audit_log.push(audit.clone());
+        Ok(())
+    }
+}
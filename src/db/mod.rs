@@ -1 +1,50 @@
+use std::sync::Arc;
+
+use crate::config::DatabaseSettings;
+
+pub mod memory;
+pub mod retry;
+pub mod sqlite;
+pub mod store;
 pub mod transactions;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+pub use store::{PgStore, PoolStats, Store, StoreError};
+
+// The scheme on database.url picks the backend - `memory:` for the in-memory store,
+// `sqlite:` for a file (or `sqlite::memory:`), anything else goes to postgres built
+// from the rest of the settings
+pub async fn connect(settings: &DatabaseSettings) -> Result<Arc<dyn Store>, sqlx::Error> {
+    match &settings.url {
+        Some(_) if settings.is_memory() => {
+            info!("Using the in-memory backend");
+            Ok(Arc::new(MemoryStore::new()))
+        }
+        Some(url) if settings.is_sqlite() => {
+            info!("Using the SQLite backend");
+            Ok(Arc::new(SqliteStore::connect(url.expose()).await?))
+        }
+        _ => {
+            info!("Using the Postgres backend");
+            let pool = settings
+                .pool_options()
+                .connect_with(settings.connect_options()?)
+                .await?;
+            Ok(Arc::new(PgStore::new(pool)))
+        }
+    }
+}
+
+// Same as connect, except postgres doesn't try to reach the server until something
+// asks for a connection, so startup can retry it on its own schedule. The other two are
+// local and just connect
+pub async fn connect_lazy(settings: &DatabaseSettings) -> Result<Arc<dyn Store>, sqlx::Error> {
+    if settings.is_memory() || settings.is_sqlite() {
+        return connect(settings).await;
+    }
+    info!("Using the Postgres backend");
+    let pool = settings
+        .pool_options()
+        .connect_lazy_with(settings.connect_options()?);
+    Ok(Arc::new(PgStore::new(pool)))
+}
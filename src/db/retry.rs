@@ -0,0 +1,157 @@
+use rand::Rng;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{connect_lazy, Store};
+use crate::config::{CohortSettings, DatabaseSettings, OnDeadline, RetrySettings};
+use crate::model;
+
+// sqlx keeps retrying a refused connection for the whole acquire timeout on its own,
+// so each attempt gets cut off here instead
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Backoff {
+    next: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(settings: &RetrySettings) -> Self {
+        Self {
+            next: Duration::from_millis(settings.initial_backoff_ms),
+            max: Duration::from_millis(settings.max_backoff_ms),
+        }
+    }
+
+    // Somewhere between half and all of the current backoff, then doubles it
+    pub fn delay(&mut self) -> Duration {
+        let half = self.next / 2;
+        let delay = half + rand::thread_rng().gen_range(Duration::ZERO..=half);
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+}
+
+// Whether the store has been migrated and had the cohorts synced. Reachable isn't enough
+// for /health/ready, a degraded start can get the db back before it's caught up
+#[derive(Clone, Debug, Default)]
+pub struct Prepared(Arc<AtomicBool>);
+
+impl Prepared {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Migrates and syncs the cohorts, retrying until the deadline. Past that it either gives
+// up or hands the store back anyway and keeps going in the background, depending on
+// database.retry.on_deadline. A bad url or option fails straight away
+pub async fn start(
+    settings: &DatabaseSettings,
+    cohorts: &[CohortSettings],
+) -> Result<(Arc<dyn Store>, Prepared), Box<dyn Error>> {
+    let store = connect_lazy(settings).await?;
+    let prepared = Prepared::new();
+    let deadline = Instant::now() + Duration::from_secs(settings.retry.deadline_secs);
+    let mut backoff = Backoff::new(&settings.retry);
+    let mut attempt = 1;
+    loop {
+        let timeout = ATTEMPT_TIMEOUT.min(deadline.saturating_duration_since(Instant::now()));
+        let error = match tokio::time::timeout(timeout, prepare(store.as_ref(), cohorts)).await {
+            Ok(Ok(())) => {
+                prepared.set();
+                return Ok((store, prepared));
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {:?}", timeout),
+        };
+
+        let delay = backoff.delay();
+        if Instant::now() + delay > deadline {
+            error!(
+                "Couldn't get the db ready after {} attempts: {}",
+                attempt, error
+            );
+            break;
+        }
+        warn!(
+            "DB isn't ready yet (attempt {}), trying again in {:?}: {}",
+            attempt, delay, error
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+
+    match settings.retry.on_deadline {
+        OnDeadline::Fail => Err("Gave up waiting on the db".into()),
+        OnDeadline::Degraded => {
+            warn!("Starting degraded, the db will be retried in the background");
+            tokio::spawn(finish(
+                store.clone(),
+                prepared.clone(),
+                cohorts.to_vec(),
+                Backoff::new(&settings.retry),
+            ));
+            Ok((store, prepared))
+        }
+    }
+}
+
+async fn prepare(store: &dyn Store, cohorts: &[CohortSettings]) -> Result<(), Box<dyn Error>> {
+    store.migrate().await?;
+    model::sync_cohorts(store, cohorts).await?;
+    Ok(())
+}
+
+// Keeps at it for as long as it takes
+async fn finish(
+    store: Arc<dyn Store>,
+    prepared: Prepared,
+    cohorts: Vec<CohortSettings>,
+    mut backoff: Backoff,
+) {
+    loop {
+        tokio::time::sleep(backoff.delay()).await;
+        let result = tokio::time::timeout(ATTEMPT_TIMEOUT, prepare(store.as_ref(), &cohorts))
+            .await
+            .map(|result| result.map_err(|e| e.to_string()));
+        match result {
+            Ok(Ok(())) => {
+                prepared.set();
+                info!("DB is back, no longer degraded");
+                return;
+            }
+            Ok(Err(e)) => warn!("Still degraded: {}", e),
+            Err(_) => warn!("Still degraded: timed out after {:?}", ATTEMPT_TIMEOUT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_max() {
+        let mut backoff = Backoff::new(&RetrySettings {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 400,
+            ..RetrySettings::default()
+        });
+        for max in [100, 200, 400, 400] {
+            let delay = backoff.delay();
+            assert!(delay >= Duration::from_millis(max / 2));
+            assert!(delay <= Duration::from_millis(max));
+        }
+    }
+}
@@ -0,0 +1,686 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteExecutor, SqlitePool, SqlitePoolOptions, SqliteRow,
+};
+use sqlx::types::Json;
+use sqlx::{query, Row, Sqlite, SqliteConnection};
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use super::store::{PoolStats, Store, StoreError};
+use super::transactions::{
+    ApplicantLookup, ApplicantRecord, ApplicantSummary, AuditEntry, ChallengeBuilder, GradeOutcome,
+    Grader, GradingContext, RegistrationWindow, SolutionRecord,
+};
+use crate::config::{ChallengeSettings, CohortSettings};
+
+// For single node deployments that don't want to run postgres. The query macros only
+// check against one database, so everything in here is a runtime query - keep them in
+// step with `transactions.rs` by hand. Timestamps are bound from rust as rfc3339 in UTC,
+// so comparing them as text works. Never let sqlite fill one in with its own clock
+#[derive(Clone)]
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    // Creates the file if it isn't there yet. Every connection to `sqlite::memory:` gets
+    // its own empty database, so that one is held to a single connection
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let max_connections = if url.contains(":memory:") { 1 } else { 10 };
+        Ok(Self {
+            pool: SqlitePoolOptions::new()
+                .max_connections(max_connections)
+                .connect_with(options)
+                .await?,
+        })
+    }
+}
+
+// Sqlite has no row locks, the closest thing is grabbing the write lock on the whole
+// database up front. A plain BEGIN only takes it at the first write, which is too late,
+// and sqlx's own transactions can't be told to start any other way in this version. So
+// this does the bookkeeping by hand: anything that doesn't reach `commit` - an error, an
+// early return, or the request getting dropped halfway - is rolled back on drop. Every
+// transaction that writes goes through here, single statements don't need it
+struct ImmediateTx {
+    conn: Option<PoolConnection<Sqlite>>,
+}
+
+impl ImmediateTx {
+    async fn begin(pool: &SqlitePool) -> Result<Self, StoreError> {
+        // The guard exists before BEGIN runs, so a BEGIN cut off midway still gets undone
+        let mut tx = Self {
+            conn: Some(pool.acquire().await?),
+        };
+        query("BEGIN IMMEDIATE;").execute(&mut *tx).await?;
+        Ok(tx)
+    }
+
+    async fn commit(mut self) -> Result<(), StoreError> {
+        query("COMMIT;").execute(&mut *self).await?;
+        self.conn = None;
+        Ok(())
+    }
+}
+
+impl Deref for ImmediateTx {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        self.conn
+            .as_deref()
+            .expect("connection is only taken on commit")
+    }
+}
+
+impl DerefMut for ImmediateTx {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        self.conn
+            .as_deref_mut()
+            .expect("connection is only taken on commit")
+    }
+}
+
+impl Drop for ImmediateTx {
+    fn drop(&mut self) {
+        let Some(mut conn) = self.conn.take() else {
+            return;
+        };
+        match tokio::runtime::Handle::try_current() {
+            // The connection only goes back to the pool once the rollback is through
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = query("ROLLBACK;").execute(&mut *conn).await {
+                        warn!("Couldn't roll back a sqlite transaction: {e}");
+                        drop(conn.detach());
+                    }
+                });
+            }
+            // Nowhere to run the rollback, so the connection can't go back to the pool.
+            // Closing it throws away whatever it was halfway through
+            Err(_) => drop(conn.detach()),
+        }
+    }
+}
+
+fn challenge_settings(row: &SqliteRow, column: &str) -> Result<ChallengeSettings, StoreError> {
+    Ok(row.try_get::<Json<ChallengeSettings>, _>(column)?.0)
+}
+
+fn applicant_summary(row: SqliteRow) -> Result<ApplicantSummary, StoreError> {
+    Ok(ApplicantSummary {
+        nuid: row.try_get("nuid")?,
+        applicant_name: row.try_get("applicant_name")?,
+        cohort_name: row.try_get("cohort_name")?,
+        registration_time: row.try_get("registration_time")?,
+        attempts: row.try_get("attempts")?,
+        first_success: row.try_get("first_success")?,
+        late: row.try_get("late")?,
+        extra_seconds: row.try_get("extra_seconds")?,
+        extra_attempts: row.try_get("extra_attempts")?,
+    })
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn migrate(&self) -> Result<(), StoreError> {
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&self.pool)
+            .await
+            .map_err(sqlx::Error::from)?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn migration_version(&self) -> Result<Option<i64>, StoreError> {
+        Ok(
+            sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+                .fetch_one(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn sync_cohort(&self, cohort: &CohortSettings) -> Result<(), StoreError> {
+        query(
+            r#"INSERT INTO cohorts (cohort_name, opens_at, closes_at, submission_deadline, challenge)
+            VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT (cohort_name) DO UPDATE SET
+            opens_at = excluded.opens_at, closes_at = excluded.closes_at,
+            submission_deadline = excluded.submission_deadline, challenge = excluded.challenge;"#,
+        )
+        .bind(&cohort.name)
+        .bind(cohort.opens_at)
+        .bind(cohort.closes_at)
+        .bind(cohort.submission_deadline)
+        .bind(Json(&cohort.challenge))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn registration_window(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<RegistrationWindow, StoreError> {
+        let row = query(
+            r#"SELECT MIN(opens_at) FILTER (WHERE opens_at > ?1) AS next_opens_at,
+            MAX(closes_at) FILTER (WHERE closes_at <= ?1) AS last_closes_at FROM cohorts;"#,
+        )
+        .bind(at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(RegistrationWindow {
+            next_opens_at: row.try_get("next_opens_at")?,
+            last_closes_at: row.try_get("last_closes_at")?,
+        })
+    }
+
+    async fn register_user(
+        &self,
+        at: DateTime<Utc>,
+        token: Uuid,
+        name: String,
+        nuid: String,
+        build: ChallengeBuilder<'_>,
+    ) -> Result<Option<Vec<String>>, StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+        let cohort = match query(
+            r#"SELECT cohort_id, challenge FROM cohorts WHERE opens_at <= ?1
+            AND (closes_at IS NULL OR closes_at > ?1) ORDER BY opens_at DESC LIMIT 1;"#,
+        )
+        .bind(at)
+        .fetch_optional(&mut *tx)
+        .await?
+        {
+            Some(cohort) => cohort,
+            None => return Ok(None),
+        };
+        let cohort_id: i32 = cohort.try_get("cohort_id")?;
+
+        let (challenge, solution) = build(cohort_id, &challenge_settings(&cohort, "challenge")?);
+        query(
+            r#"INSERT INTO applicants (cohort_id, nuid, applicant_name, registration_time,
+            token, challenge, solution) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);"#,
+        )
+        .bind(cohort_id)
+        .bind(nuid)
+        .bind(name)
+        .bind(at)
+        .bind(token)
+        .bind(Json(&challenge))
+        .bind(Json(solution))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(challenge))
+    }
+
+    async fn grade_submission(
+        &self,
+        token: Uuid,
+        submission_time: DateTime<Utc>,
+        grade: Grader<'_>,
+    ) -> Result<GradeOutcome, StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+        let applicant = query(
+            r#"SELECT cohort_id, nuid, solution, cohorts.challenge AS cohort_challenge,
+            submission_deadline,
+            (SELECT COALESCE(SUM(extra_seconds), 0) FROM extensions WHERE
+            extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid)
+            AS extra_seconds,
+            (SELECT COALESCE(SUM(extra_attempts), 0) FROM extensions WHERE
+            extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid)
+            AS extra_attempts,
+            (SELECT COUNT(*) FROM submissions WHERE
+            submissions.cohort_id=applicants.cohort_id AND submissions.nuid=applicants.nuid)
+            AS attempts
+            FROM applicants JOIN cohorts using(cohort_id) WHERE token=?1"#,
+        )
+        .bind(token)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let graded = grade(&GradingContext {
+            solution: applicant.try_get::<Json<Vec<String>>, _>("solution")?.0,
+            challenge: challenge_settings(&applicant, "cohort_challenge")?,
+            deadline: applicant.try_get("submission_deadline")?,
+            extension: (
+                applicant.try_get("extra_seconds")?,
+                applicant.try_get("extra_attempts")?,
+            ),
+            attempts: applicant.try_get("attempts")?,
+        });
+
+        if let GradeOutcome::Graded { ok, late } = graded {
+            query(
+                r#"INSERT INTO submissions (cohort_id, nuid, ok, submission_time, late)
+                VALUES (?1, ?2, ?3, ?4, ?5);"#,
+            )
+            .bind(applicant.try_get::<i32, _>("cohort_id")?)
+            .bind(applicant.try_get::<String, _>("nuid")?)
+            .bind(ok)
+            .bind(submission_time)
+            .bind(late)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(graded)
+    }
+
+    // No DISTINCT ON or ANY here - the latest submission comes from a window function,
+    // and the nuids go in as a json array that json_each unpacks
+    async fn get_applicants(
+        &self,
+        lookup: ApplicantLookup<'_>,
+        cohort: Option<&str>,
+    ) -> Result<Vec<ApplicantRecord>, StoreError> {
+        let rows = query(
+            r#"SELECT applicants.nuid, applicant_name, cohort_name, registration_time,
+            submission_deadline, json_extract(cohorts.challenge, '$.max_attempts') AS max_attempts,
+            latest.submission_time, latest.ok, latest.late,
+            (SELECT COUNT(*) FROM submissions WHERE submissions.cohort_id=applicants.cohort_id
+            AND submissions.nuid=applicants.nuid) AS attempts,
+            (SELECT MIN(submission_time) FROM submissions WHERE
+            submissions.cohort_id=applicants.cohort_id AND submissions.nuid=applicants.nuid
+            AND ok) AS first_success,
+            COALESCE((SELECT SUM(extra_seconds) FROM extensions WHERE
+            extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)
+            AS extra_seconds,
+            COALESCE((SELECT SUM(extra_attempts) FROM extensions WHERE
+            extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)
+            AS extra_attempts
+            FROM applicants JOIN cohorts ON cohorts.cohort_id=applicants.cohort_id
+            LEFT JOIN (SELECT cohort_id, nuid, ok, late, submission_time, ROW_NUMBER() OVER
+            (PARTITION BY cohort_id, nuid ORDER BY submission_time DESC) AS newest
+            FROM submissions) AS latest ON latest.cohort_id=applicants.cohort_id
+            AND latest.nuid=applicants.nuid AND latest.newest = 1
+            WHERE (?1 IS NULL OR applicants.nuid IN (SELECT value FROM json_each(?1)))
+            AND (?2 IS NULL OR token=?2) AND (?3 IS NULL OR cohort_name=?3)
+            ORDER BY opens_at, applicants.cohort_id, applicants.nuid;"#,
+        )
+        .bind(lookup.nuids().map(Json))
+        .bind(lookup.token())
+        .bind(cohort)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(ApplicantRecord {
+                    nuid: row.try_get("nuid")?,
+                    applicant_name: row.try_get("applicant_name")?,
+                    cohort_name: row.try_get("cohort_name")?,
+                    registration_time: row.try_get("registration_time")?,
+                    submission_deadline: row.try_get("submission_deadline")?,
+                    max_attempts: row.try_get("max_attempts")?,
+                    submission_time: row.try_get("submission_time")?,
+                    ok: row.try_get("ok")?,
+                    late: row.try_get("late")?,
+                    attempts: row.try_get("attempts")?,
+                    first_success: row.try_get("first_success")?,
+                    extra_seconds: row.try_get("extra_seconds")?,
+                    extra_attempts: row.try_get("extra_attempts")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn grant_extension(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+        extra_seconds: i64,
+        extra_attempts: i64,
+        reason: &str,
+        granted_at: DateTime<Utc>,
+    ) -> Result<(String, i64, i64, Option<DateTime<Utc>>), StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+
+        let granted = query(
+            r#"INSERT INTO extensions (cohort_id, nuid, extra_seconds, extra_attempts, reason,
+            granted_at) SELECT cohort_id, nuid, ?3, ?4, ?5, ?6 FROM applicants
+            JOIN cohorts using(cohort_id) WHERE nuid=?1 AND (?2 IS NULL OR cohort_name=?2)
+            ORDER BY registration_time DESC LIMIT 1 RETURNING cohort_id;"#,
+        )
+        .bind(nuid)
+        .bind(cohort)
+        .bind(extra_seconds)
+        .bind(extra_attempts)
+        .bind(reason)
+        .bind(granted_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        let cohort_id: i32 = granted.try_get("cohort_id")?;
+
+        let row = query(
+            r#"SELECT cohort_name, submission_deadline,
+            COALESCE(SUM(extra_seconds), 0) AS extra_seconds,
+            COALESCE(SUM(extra_attempts), 0) AS extra_attempts FROM cohorts
+            JOIN extensions using(cohort_id) WHERE cohort_id=?1 AND nuid=?2
+            GROUP BY cohort_id;"#,
+        )
+        .bind(cohort_id)
+        .bind(nuid)
+        .fetch_one(&mut *tx)
+        .await?;
+        let extra_seconds: i64 = row.try_get("extra_seconds")?;
+        let deadline = row
+            .try_get::<Option<DateTime<Utc>>, _>("submission_deadline")?
+            .map(|deadline| deadline + chrono::Duration::seconds(extra_seconds));
+
+        query(
+            r#"UPDATE submissions SET late = (?3 IS NOT NULL AND submission_time > ?3)
+            WHERE cohort_id=?1 AND nuid=?2;"#,
+        )
+        .bind(cohort_id)
+        .bind(nuid)
+        .bind(deadline)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((
+            row.try_get("cohort_name")?,
+            extra_seconds,
+            row.try_get("extra_attempts")?,
+            deadline,
+        ))
+    }
+
+    async fn retreive_token(&self, nuid: &str) -> Result<Uuid, StoreError> {
+        query(
+            r#"SELECT token FROM applicants WHERE nuid=?1 ORDER BY registration_time DESC LIMIT 1"#,
+        )
+        .bind(nuid)
+        .fetch_one(&self.pool)
+        .await?
+        .try_get("token")
+        .map_err(StoreError::from)
+    }
+
+    async fn retreive_challenge(&self, token: Uuid) -> Result<Vec<String>, StoreError> {
+        let row = query(r#"SELECT challenge FROM applicants where token=?1"#)
+            .bind(token)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(row.try_get::<Json<Vec<String>>, _>("challenge")?.0)
+    }
+
+    async fn retreive_soln(&self, token: Uuid) -> Result<SolutionRecord, StoreError> {
+        let row = query(
+            r#"SELECT cohort_id, nuid, solution, cohorts.challenge AS cohort_challenge,
+            submission_deadline FROM applicants JOIN cohorts using(cohort_id) WHERE token=?1"#,
+        )
+        .bind(token)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(SolutionRecord {
+            solution: row.try_get::<Json<Vec<String>>, _>("solution")?.0,
+            cohort_id: row.try_get("cohort_id")?,
+            nuid: row.try_get("nuid")?,
+            challenge: challenge_settings(&row, "cohort_challenge")?,
+            submission_deadline: row.try_get("submission_deadline")?,
+        })
+    }
+
+    async fn find_applicant(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+    ) -> Result<(i32, String, ChallengeSettings), StoreError> {
+        let row = query(
+            r#"SELECT cohort_id, cohort_name, cohorts.challenge AS cohort_challenge FROM applicants
+            JOIN cohorts using(cohort_id) WHERE nuid=?1 AND (?2 IS NULL OR cohort_name=?2)
+            ORDER BY registration_time DESC LIMIT 1;"#,
+        )
+        .bind(nuid)
+        .bind(cohort)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((
+            row.try_get("cohort_id")?,
+            row.try_get("cohort_name")?,
+            challenge_settings(&row, "cohort_challenge")?,
+        ))
+    }
+
+    // LIKE is already case insensitive in sqlite, at least for ascii
+    async fn list_applicants(
+        &self,
+        cohort: Option<&str>,
+        search: Option<&str>,
+    ) -> Result<Vec<ApplicantSummary>, StoreError> {
+        let rows = query(
+            r#"SELECT nuid, applicant_name, cohort_name, registration_time,
+            (SELECT COUNT(*) FROM submissions WHERE submissions.cohort_id=applicants.cohort_id
+            AND submissions.nuid=applicants.nuid) AS attempts,
+            (SELECT MIN(submission_time) FROM submissions WHERE
+            submissions.cohort_id=applicants.cohort_id AND submissions.nuid=applicants.nuid
+            AND ok) AS first_success,
+            COALESCE((SELECT late FROM submissions WHERE submissions.cohort_id=applicants.cohort_id
+            AND submissions.nuid=applicants.nuid ORDER BY submission_time DESC LIMIT 1), false)
+            AS late,
+            COALESCE((SELECT SUM(extra_seconds) FROM extensions WHERE
+            extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)
+            AS extra_seconds,
+            COALESCE((SELECT SUM(extra_attempts) FROM extensions WHERE
+            extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)
+            AS extra_attempts
+            FROM applicants JOIN cohorts ON cohorts.cohort_id=applicants.cohort_id
+            WHERE (?1 IS NULL OR cohort_name=?1)
+            AND (?2 IS NULL OR nuid LIKE '%' || ?2 || '%'
+            OR applicant_name LIKE '%' || ?2 || '%')
+            ORDER BY applicants.cohort_id, nuid;"#,
+        )
+        .bind(cohort)
+        .bind(search)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(applicant_summary).collect()
+    }
+
+    async fn reset_submissions(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+
+        let result = query(r#"DELETE FROM submissions WHERE cohort_id=?1 AND nuid=?2;"#)
+            .bind(cohort_id)
+            .bind(nuid)
+            .execute(&mut *tx)
+            .await?;
+        write_audit_log(&mut *tx, audit).await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn replace_challenge(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        challenge: &[String],
+        solution: &[String],
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+
+        query(r#"DELETE FROM submissions WHERE cohort_id=?1 AND nuid=?2;"#)
+            .bind(cohort_id)
+            .bind(nuid)
+            .execute(&mut *tx)
+            .await?;
+        query(r#"UPDATE applicants SET challenge=?3, solution=?4 WHERE cohort_id=?1 AND nuid=?2;"#)
+            .bind(cohort_id)
+            .bind(nuid)
+            .bind(Json(challenge))
+            .bind(Json(solution))
+            .execute(&mut *tx)
+            .await?;
+        write_audit_log(&mut *tx, audit).await?;
+
+        tx.commit().await
+    }
+
+    async fn replace_token(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        token: Uuid,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+
+        query(r#"UPDATE applicants SET token=?3 WHERE cohort_id=?1 AND nuid=?2;"#)
+            .bind(cohort_id)
+            .bind(nuid)
+            .bind(token)
+            .execute(&mut *tx)
+            .await?;
+        write_audit_log(&mut *tx, audit).await?;
+
+        tx.commit().await
+    }
+
+    async fn delete_applicant(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+
+        for table in ["extensions", "submissions", "applicants"] {
+            query(&format!(
+                "DELETE FROM {} WHERE cohort_id=?1 AND nuid=?2;",
+                table
+            ))
+            .bind(cohort_id)
+            .bind(nuid)
+            .execute(&mut *tx)
+            .await?;
+        }
+        write_audit_log(&mut *tx, audit).await?;
+
+        tx.commit().await
+    }
+
+    async fn create_api_key(
+        &self,
+        name: &str,
+        key_hash: &str,
+        created_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+
+        query(r#"INSERT INTO api_keys (key_name, key_hash, created_at) VALUES (?1, ?2, ?3);"#)
+            .bind(name)
+            .bind(key_hash)
+            .bind(created_at)
+            .execute(&mut *tx)
+            .await?;
+        write_audit_log(&mut *tx, audit).await?;
+
+        tx.commit().await
+    }
+
+    async fn revoke_api_key(
+        &self,
+        name: &str,
+        revoked_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError> {
+        let mut tx = ImmediateTx::begin(&self.pool).await?;
+
+        let result =
+            query(r#"UPDATE api_keys SET revoked_at=?2 WHERE key_name=?1 AND revoked_at IS NULL;"#)
+                .bind(name)
+                .bind(revoked_at)
+                .execute(&mut *tx)
+                .await?;
+        if result.rows_affected() == 0 {
+            return Ok(0);
+        }
+        write_audit_log(&mut *tx, audit).await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn api_key_exists(&self, key_hash: &str) -> Result<bool, StoreError> {
+        query(
+            r#"SELECT EXISTS(SELECT 1 FROM api_keys WHERE key_hash=?1 AND revoked_at IS NULL)
+            AS found;"#,
+        )
+        .bind(key_hash)
+        .fetch_one(&self.pool)
+        .await?
+        .try_get("found")
+        .map_err(StoreError::from)
+    }
+
+    async fn write_audit_log(&self, audit: &AuditEntry) -> Result<(), StoreError> {
+        write_audit_log(&self.pool, audit).await
+    }
+}
+
+// Same as `transactions::write_audit_log_db`, on the pool or inside a transaction
+async fn write_audit_log<'e>(
+    executor: impl SqliteExecutor<'e>,
+    audit: &AuditEntry,
+) -> Result<(), StoreError> {
+    query(
+        r#"INSERT INTO audit_log (actor, action, detail, performed_at)
+        VALUES (?1, ?2, ?3, ?4);"#,
+    )
+    .bind(&audit.actor)
+    .bind(&audit.action)
+    .bind(Json(&audit.detail))
+    .bind(audit.performed_at)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,379 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::transactions::{
+    self, ApplicantLookup, ApplicantRecord, ApplicantSummary, AuditEntry, ChallengeBuilder,
+    GradeOutcome, Grader, RegistrationWindow, SolutionRecord,
+};
+use crate::config::{ChallengeSettings, CohortSettings};
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+// What every backend fails with. The model only ever needs to tell the first two apart,
+// so that's all a backend without sqlx underneath has to come up with
+#[derive(thiserror::Error, Debug)]
+pub enum StoreError {
+    #[error("no such row")]
+    NotFound,
+    #[error("that already exists")]
+    Duplicate,
+    #[error(transparent)]
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::Database(e) if e.is_unique_violation() => Self::Duplicate,
+            e => Self::Db(e),
+        }
+    }
+}
+
+// Everything the model needs from a database. Postgres is the real one, the in-memory
+// one in `memory.rs` is there so the endpoints can be tested without a db running
+#[async_trait]
+pub trait Store: Send + Sync {
+    // Brings the schema up to date, each backend has its own migrations folder
+    async fn migrate(&self) -> Result<(), StoreError>;
+
+    // Waits for checked out connections to come back, then closes them all
+    async fn close(&self);
+
+    // Which backend this is, for the readiness check
+    fn backend(&self) -> &'static str;
+
+    // The cheapest round trip there is, so readiness knows the db is actually there
+    async fn ping(&self) -> Result<(), StoreError>;
+
+    // None when there's no pool to speak of
+    fn pool_stats(&self) -> Option<PoolStats>;
+
+    // Latest migration that's been applied, None if there's nothing to migrate
+    async fn migration_version(&self) -> Result<Option<i64>, StoreError>;
+
+    async fn sync_cohort(&self, cohort: &CohortSettings) -> Result<(), StoreError>;
+
+    async fn registration_window(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<RegistrationWindow, StoreError>;
+
+    // Registers into whichever cohort is open at `at`, None if nothing is
+    async fn register_user(
+        &self,
+        at: DateTime<Utc>,
+        token: Uuid,
+        name: String,
+        nuid: String,
+        build: ChallengeBuilder<'_>,
+    ) -> Result<Option<Vec<String>>, StoreError>;
+
+    // Grades and records a submission atomically, see `transactions::grade_submission_db`
+    async fn grade_submission(
+        &self,
+        token: Uuid,
+        submission_time: DateTime<Utc>,
+        grade: Grader<'_>,
+    ) -> Result<GradeOutcome, StoreError>;
+
+    async fn get_applicants(
+        &self,
+        lookup: ApplicantLookup<'_>,
+        cohort: Option<&str>,
+    ) -> Result<Vec<ApplicantRecord>, StoreError>;
+
+    async fn grant_extension(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+        extra_seconds: i64,
+        extra_attempts: i64,
+        reason: &str,
+        granted_at: DateTime<Utc>,
+    ) -> Result<(String, i64, i64, Option<DateTime<Utc>>), StoreError>;
+
+    async fn retreive_token(&self, nuid: &str) -> Result<Uuid, StoreError>;
+
+    async fn retreive_challenge(&self, token: Uuid) -> Result<Vec<String>, StoreError>;
+
+    async fn retreive_soln(&self, token: Uuid) -> Result<SolutionRecord, StoreError>;
+
+    async fn find_applicant(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+    ) -> Result<(i32, String, ChallengeSettings), StoreError>;
+
+    async fn list_applicants(
+        &self,
+        cohort: Option<&str>,
+        search: Option<&str>,
+    ) -> Result<Vec<ApplicantSummary>, StoreError>;
+
+    // Everything from here down that changes something writes `audit` along with it,
+    // all or nothing
+    async fn reset_submissions(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError>;
+
+    async fn replace_challenge(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        challenge: &[String],
+        solution: &[String],
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError>;
+
+    async fn replace_token(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        token: Uuid,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError>;
+
+    async fn delete_applicant(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError>;
+
+    async fn create_api_key(
+        &self,
+        name: &str,
+        key_hash: &str,
+        created_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError>;
+
+    // Only audits when there was a key to revoke
+    async fn revoke_api_key(
+        &self,
+        name: &str,
+        revoked_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError>;
+
+    async fn api_key_exists(&self, key_hash: &str) -> Result<bool, StoreError>;
+
+    // For the actions that don't change anything, and the ones that failed
+    async fn write_audit_log(&self, audit: &AuditEntry) -> Result<(), StoreError>;
+}
+
+// Just hands everything off to `transactions`. Cloning it is cheap, PgPool is an Arc inside
+#[derive(Clone)]
+pub struct PgStore {
+    pool: PgPool,
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Store for PgStore {
+    async fn migrate(&self) -> Result<(), StoreError> {
+        sqlx::migrate!("./migrations/postgres")
+            .run(&self.pool)
+            .await
+            .map_err(sqlx::Error::from)?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(transactions::ping_db(&self.pool).await?)
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn migration_version(&self) -> Result<Option<i64>, StoreError> {
+        Ok(transactions::migration_version_db(&self.pool).await?)
+    }
+
+    async fn sync_cohort(&self, cohort: &CohortSettings) -> Result<(), StoreError> {
+        Ok(transactions::sync_cohort_db(&self.pool, cohort).await?)
+    }
+
+    async fn registration_window(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<RegistrationWindow, StoreError> {
+        Ok(transactions::registration_window_db(&self.pool, at).await?)
+    }
+
+    async fn register_user(
+        &self,
+        at: DateTime<Utc>,
+        token: Uuid,
+        name: String,
+        nuid: String,
+        build: ChallengeBuilder<'_>,
+    ) -> Result<Option<Vec<String>>, StoreError> {
+        Ok(transactions::register_user_db(&self.pool, at, token, name, nuid, build).await?)
+    }
+
+    async fn grade_submission(
+        &self,
+        token: Uuid,
+        submission_time: DateTime<Utc>,
+        grade: Grader<'_>,
+    ) -> Result<GradeOutcome, StoreError> {
+        Ok(transactions::grade_submission_db(&self.pool, token, submission_time, grade).await?)
+    }
+
+    async fn get_applicants(
+        &self,
+        lookup: ApplicantLookup<'_>,
+        cohort: Option<&str>,
+    ) -> Result<Vec<ApplicantRecord>, StoreError> {
+        Ok(transactions::get_applicants_db(&self.pool, lookup, cohort).await?)
+    }
+
+    async fn grant_extension(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+        extra_seconds: i64,
+        extra_attempts: i64,
+        reason: &str,
+        granted_at: DateTime<Utc>,
+    ) -> Result<(String, i64, i64, Option<DateTime<Utc>>), StoreError> {
+        Ok(transactions::grant_extension_db(
+            &self.pool,
+            nuid,
+            cohort,
+            extra_seconds,
+            extra_attempts,
+            reason,
+            granted_at,
+        )
+        .await?)
+    }
+
+    async fn retreive_token(&self, nuid: &str) -> Result<Uuid, StoreError> {
+        Ok(transactions::retreive_token_db(&self.pool, nuid).await?)
+    }
+
+    async fn retreive_challenge(&self, token: Uuid) -> Result<Vec<String>, StoreError> {
+        Ok(transactions::retreive_challenge_db(&self.pool, token).await?)
+    }
+
+    async fn retreive_soln(&self, token: Uuid) -> Result<SolutionRecord, StoreError> {
+        Ok(transactions::retreive_soln(&self.pool, token).await?)
+    }
+
+    async fn find_applicant(
+        &self,
+        nuid: &str,
+        cohort: Option<&str>,
+    ) -> Result<(i32, String, ChallengeSettings), StoreError> {
+        Ok(transactions::find_applicant_db(&self.pool, nuid, cohort).await?)
+    }
+
+    async fn list_applicants(
+        &self,
+        cohort: Option<&str>,
+        search: Option<&str>,
+    ) -> Result<Vec<ApplicantSummary>, StoreError> {
+        Ok(transactions::list_applicants_db(&self.pool, cohort, search).await?)
+    }
+
+    async fn reset_submissions(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError> {
+        Ok(transactions::reset_submissions_db(&self.pool, cohort_id, nuid, audit).await?)
+    }
+
+    async fn replace_challenge(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        challenge: &[String],
+        solution: &[String],
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        Ok(transactions::replace_challenge_db(
+            &self.pool, cohort_id, nuid, challenge, solution, audit,
+        )
+        .await?)
+    }
+
+    async fn replace_token(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        token: Uuid,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        Ok(transactions::replace_token_db(&self.pool, cohort_id, nuid, token, audit).await?)
+    }
+
+    async fn delete_applicant(
+        &self,
+        cohort_id: i32,
+        nuid: &str,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        Ok(transactions::delete_applicant_db(&self.pool, cohort_id, nuid, audit).await?)
+    }
+
+    async fn create_api_key(
+        &self,
+        name: &str,
+        key_hash: &str,
+        created_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<(), StoreError> {
+        Ok(transactions::create_api_key_db(&self.pool, name, key_hash, created_at, audit).await?)
+    }
+
+    async fn revoke_api_key(
+        &self,
+        name: &str,
+        revoked_at: DateTime<Utc>,
+        audit: &AuditEntry,
+    ) -> Result<u64, StoreError> {
+        Ok(transactions::revoke_api_key_db(&self.pool, name, revoked_at, audit).await?)
+    }
+
+    async fn api_key_exists(&self, key_hash: &str) -> Result<bool, StoreError> {
+        Ok(transactions::api_key_exists_db(&self.pool, key_hash).await?)
+    }
+
+    async fn write_audit_log(&self, audit: &AuditEntry) -> Result<(), StoreError> {
+        Ok(transactions::write_audit_log_db(&self.pool, audit).await?)
+    }
+}
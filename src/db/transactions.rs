@@ -1,121 +1,690 @@
 use chrono::{DateTime, Utc};
-use core::panic;
+use serde::de::DeserializeOwned;
 use serde_json;
-use std::time::SystemTime;
 use uuid::Uuid;
 
-use sqlx::{PgPool, query};
+use sqlx::{query, query_as, types::Json, PgExecutor, PgPool};
+use tracing::instrument;
 
+use crate::config::{ChallengeSettings, CohortSettings};
+
+// These two don't go through query! - the macros are checked against a db that was set
+// up with psql, so _sqlx_migrations doesn't exist there
+#[instrument(skip_all)]
+pub async fn ping_db(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+#[instrument(skip_all)]
+pub async fn migration_version_db(pool: &PgPool) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pool)
+        .await
+}
+
+#[instrument(skip_all)]
+pub async fn sync_cohort_db(pool: &PgPool, cohort: &CohortSettings) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO cohorts (cohort_name, opens_at, closes_at, submission_deadline, challenge)
+        VALUES ($1, $2, $3, $4, $5) ON CONFLICT (cohort_name) DO UPDATE SET
+        opens_at = EXCLUDED.opens_at, closes_at = EXCLUDED.closes_at,
+        submission_deadline = EXCLUDED.submission_deadline, challenge = EXCLUDED.challenge;"#,
+        cohort.name,
+        cohort.opens_at,
+        cohort.closes_at,
+        cohort.submission_deadline,
+        Json(&cohort.challenge) as _,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// The next time a cohort opens and the last time one closed
+pub struct RegistrationWindow {
+    pub next_opens_at: Option<DateTime<Utc>>,
+    pub last_closes_at: Option<DateTime<Utc>>,
+}
+
+// When nothing is open, figure out whether registration hasn't started yet or is over
+#[instrument(skip_all)]
+pub async fn registration_window_db(
+    pool: &PgPool,
+    at: DateTime<Utc>,
+) -> Result<RegistrationWindow, sqlx::Error> {
+    query_as!(
+        RegistrationWindow,
+        r#"SELECT MIN(opens_at) FILTER (WHERE opens_at > $1) AS next_opens_at,
+        MAX(closes_at) FILTER (WHERE closes_at <= $1) AS last_closes_at FROM cohorts;"#,
+        at
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// The jsonb columns only ever hold what we wrote, but a bad row should fail the query
+// like any other bad column rather than take the server down
+fn from_json<T: DeserializeOwned>(value: serde_json::Value) -> Result<T, sqlx::Error> {
+    serde_json::from_value(value).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+// Builds the challenge strings and solution for whichever cohort registration lands in
+pub type ChallengeBuilder<'a> =
+    &'a (dyn Fn(i32, &ChallengeSettings) -> (Vec<String>, Vec<String>) + Send + Sync);
+
+// Registers into the cohort that's open at `at`, or returns None if nothing is. If the
+// windows overlap the most recently opened cohort wins. Nothing gets locked - the
+// applicants primary key is what turns away a second registration racing this one
+#[instrument(skip_all)]
 pub async fn register_user_db(
     pool: &PgPool,
+    at: DateTime<Utc>,
     token: Uuid,
     name: String,
     nuid: String,
-    challenge: &Vec<String>,
-    solution: Vec<String>,
-) -> Result<(), sqlx::Error> {
-    let registration_time: DateTime<Utc> = SystemTime::now().into();
-    let ser_challenge = match serde_json::to_value(challenge) {
-        Ok(val) => val,
-        Err(_) => todo!("Figure out how to handle the serde error properly"),
-    };
-    let ser_solution = match serde_json::to_value(&solution) {
-        Ok(val) => val,
-        Err(_) => todo!("Figure out how to handle the serde error properly"),
+    build: ChallengeBuilder<'_>,
+) -> Result<Option<Vec<String>>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let cohort = match query!(
+        r#"SELECT cohort_id, challenge FROM cohorts WHERE opens_at <= $1
+        AND (closes_at IS NULL OR closes_at > $1) ORDER BY opens_at DESC LIMIT 1;"#,
+        at
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    {
+        Some(cohort) => cohort,
+        None => return Ok(None),
     };
 
+    let (challenge, solution) = build(cohort.cohort_id, &from_json(cohort.challenge)?);
+
     query!(
-        r#"INSERT INTO applicants (nuid, applicant_name, registration_time, token, challenge, solution)
-        VALUES ($1, $2, $3, $4, $5, $6);"#,
+        r#"INSERT INTO applicants (cohort_id, nuid, applicant_name, registration_time, token,
+        challenge, solution) VALUES ($1, $2, $3, $4, $5, $6, $7);"#,
+        cohort.cohort_id,
         nuid,
         name,
-        registration_time,
+        at,
         token,
-        ser_challenge,
-        ser_solution,
+        Json(&challenge) as _,
+        Json(&solution) as _,
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
 
-    Ok(())
+    tx.commit().await?;
+
+    Ok(Some(challenge))
+}
+
+// Who get_applicants_db is after - reviewers look people up by nuid, applicants
+// checking their own status by token
+#[derive(Clone, Copy)]
+pub enum ApplicantLookup<'a> {
+    Nuids(&'a [String]),
+    Token(Uuid),
+}
+
+impl ApplicantLookup<'_> {
+    pub fn nuids(&self) -> Option<&[String]> {
+        match self {
+            ApplicantLookup::Nuids(nuids) => Some(nuids),
+            ApplicantLookup::Token(_) => None,
+        }
+    }
+
+    pub fn token(&self) -> Option<Uuid> {
+        match self {
+            ApplicantLookup::Nuids(_) => None,
+            ApplicantLookup::Token(token) => Some(*token),
+        }
+    }
 }
 
+// Too many columns for a tuple to stay readable. submission_time, ok and late are from
+// the latest submission, so they're all None until there's been one
+pub struct ApplicantRecord {
+    pub nuid: String,
+    pub applicant_name: String,
+    pub cohort_name: String,
+    pub registration_time: DateTime<Utc>,
+    pub submission_deadline: Option<DateTime<Utc>>,
+    pub max_attempts: Option<i64>,
+    pub submission_time: Option<DateTime<Utc>>,
+    pub ok: Option<bool>,
+    pub late: Option<bool>,
+    pub attempts: i64,
+    pub first_success: Option<DateTime<Utc>>,
+    pub extra_seconds: i64,
+    pub extra_attempts: i64,
+}
+
+// One row per applicant per cohort, in the order the cohorts opened so the last one
+// is the latest
+#[instrument(skip_all)]
 pub async fn get_applicants_db(
     pool: &PgPool,
-    nuids: &[String],
-) -> Result<Vec<(String, String, DateTime<Utc>, DateTime<Utc>, bool)>, sqlx::Error> {
+    lookup: ApplicantLookup<'_>,
+    cohort: Option<&str>,
+) -> Result<Vec<ApplicantRecord>, sqlx::Error> {
     // This is a hack, sqlx doesn't support vector replacement into an IN statement
-    let records = query!(
-        r#"SELECT DISTINCT ON (nuid) nuid, applicant_name, ok, submission_time, 
-        registration_time FROM submissions JOIN applicants using(nuid) where 
-        nuid=ANY($1) ORDER BY nuid, submission_time DESC;"#,
-        &nuids[..]
+    query_as!(
+        ApplicantRecord,
+        r#"SELECT DISTINCT ON (opens_at, applicants.cohort_id, applicants.nuid) applicants.nuid,
+        applicant_name, cohort_name, registration_time,
+        submission_deadline AS "submission_deadline?",
+        (cohorts.challenge->>'max_attempts')::bigint AS "max_attempts?",
+        submissions.submission_time AS "submission_time?", submissions.ok AS "ok?",
+        submissions.late AS "late?",
+        (SELECT COUNT(*) FROM submissions AS s WHERE s.cohort_id=applicants.cohort_id
+        AND s.nuid=applicants.nuid) AS "attempts!",
+        (SELECT MIN(s.submission_time) FROM submissions AS s WHERE
+        s.cohort_id=applicants.cohort_id AND s.nuid=applicants.nuid AND s.ok)
+        AS "first_success?",
+        COALESCE((SELECT SUM(extra_seconds) FROM extensions WHERE
+        extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)::bigint
+        AS "extra_seconds!",
+        COALESCE((SELECT SUM(extra_attempts) FROM extensions WHERE
+        extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)::bigint
+        AS "extra_attempts!"
+        FROM applicants JOIN cohorts ON cohorts.cohort_id=applicants.cohort_id
+        LEFT JOIN submissions ON submissions.cohort_id=applicants.cohort_id
+        AND submissions.nuid=applicants.nuid
+        WHERE ($1::varchar[] IS NULL OR applicants.nuid=ANY($1))
+        AND ($2::uuid IS NULL OR token=$2) AND ($3::varchar IS NULL OR cohort_name=$3)
+        ORDER BY opens_at, applicants.cohort_id, applicants.nuid,
+        submissions.submission_time DESC NULLS LAST;"#,
+        lookup.nuids(),
+        lookup.token(),
+        cohort
     )
     .fetch_all(pool)
+    .await
+}
+
+// Grants go to the applicant's most recent cohort unless one is named. The late flags
+// on their submissions get recomputed against the new deadline. Returns the cohort
+// the grant went to and the applicant's new totals
+#[instrument(skip_all)]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub async fn grant_extension_db(
+    pool: &PgPool,
+    nuid: &str,
+    cohort: Option<&str>,
+    extra_seconds: i64,
+    extra_attempts: i64,
+    reason: &str,
+    granted_at: DateTime<Utc>,
+) -> Result<(String, i64, i64, Option<DateTime<Utc>>), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let granted = query!(
+        r#"INSERT INTO extensions (cohort_id, nuid, extra_seconds, extra_attempts, reason,
+        granted_at) SELECT cohort_id, nuid, $3, $4, $5, $6 FROM applicants
+        JOIN cohorts using(cohort_id) WHERE nuid=$1 AND ($2::varchar IS NULL OR cohort_name=$2)
+        ORDER BY registration_time DESC LIMIT 1 RETURNING cohort_id;"#,
+        nuid,
+        cohort,
+        extra_seconds,
+        extra_attempts,
+        reason,
+        granted_at,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let record = query!(
+        r#"SELECT cohort_name, submission_deadline,
+        COALESCE(SUM(extra_seconds), 0)::bigint AS "extra_seconds!",
+        COALESCE(SUM(extra_attempts), 0)::bigint AS "extra_attempts!" FROM cohorts
+        JOIN extensions using(cohort_id) WHERE cohort_id=$1 AND nuid=$2
+        GROUP BY cohort_id;"#,
+        granted.cohort_id,
+        nuid,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let deadline = record
+        .submission_deadline
+        .map(|deadline| deadline + chrono::Duration::seconds(record.extra_seconds));
+
+    query!(
+        r#"UPDATE submissions SET late = ($3::timestamptz IS NOT NULL AND submission_time > $3)
+        WHERE cohort_id=$1 AND nuid=$2;"#,
+        granted.cohort_id,
+        nuid,
+        deadline,
+    )
+    .execute(&mut *tx)
     .await?;
 
-    Ok(records
-        .iter()
-        .map(|record| {
-            (
-                record.nuid.clone(),
-                record.applicant_name.clone(),
-                record.registration_time,
-                record.submission_time,
-                record.ok,
-            )
-        })
-        .collect())
+    tx.commit().await?;
+
+    Ok((
+        record.cohort_name,
+        record.extra_seconds,
+        record.extra_attempts,
+        deadline,
+    ))
 }
 
-pub async fn retreive_token_db(pool: &PgPool, nuid: &String) -> Result<Uuid, sqlx::Error> {
-    let record = query!(r#"SELECT token FROM applicants WHERE nuid=$1"#, nuid)
-        .fetch_one(pool)
-        .await?;
+// Someone who applied to more than one cohort gets their most recent token back
+#[instrument(skip_all)]
+pub async fn retreive_token_db(pool: &PgPool, nuid: &str) -> Result<Uuid, sqlx::Error> {
+    let record = query!(
+        r#"SELECT token FROM applicants WHERE nuid=$1 ORDER BY registration_time DESC LIMIT 1"#,
+        nuid
+    )
+    .fetch_one(pool)
+    .await?;
 
     Ok(record.token)
 }
 
+#[instrument(skip_all)]
 pub async fn retreive_challenge_db(pool: &PgPool, token: Uuid) -> Result<Vec<String>, sqlx::Error> {
     let record = query!(r#"SELECT challenge FROM applicants where token=$1"#, token)
         .fetch_one(pool)
         .await?;
 
-    match serde_json::from_value(record.challenge) {
-        Ok(challenge_strings) => Ok(challenge_strings),
-        Err(_e) => {
-            panic!("challenge didn't deserialize properly - this should never happen")
-        }
-    }
+    from_json(record.challenge)
 }
 
-pub async fn retreive_soln(
-    pool: &PgPool,
-    token: Uuid,
-) -> Result<(Vec<String>, String), sqlx::Error> {
+// An applicant's answer along with what it gets graded against
+pub struct SolutionRecord {
+    pub solution: Vec<String>,
+    pub cohort_id: i32,
+    pub nuid: String,
+    pub challenge: ChallengeSettings,
+    pub submission_deadline: Option<DateTime<Utc>>,
+}
+
+#[instrument(skip_all)]
+pub async fn retreive_soln(pool: &PgPool, token: Uuid) -> Result<SolutionRecord, sqlx::Error> {
     let record = query!(
-        r#"SELECT nuid, solution FROM applicants WHERE token=$1"#,
+        r#"SELECT cohort_id, nuid, solution, cohorts.challenge AS cohort_challenge,
+        submission_deadline FROM applicants JOIN cohorts using(cohort_id) WHERE token=$1"#,
         token
     )
     .fetch_one(pool)
     .await?;
 
-    match serde_json::from_value(record.solution) {
-        Ok(soln) => Ok((soln, record.nuid)),
-        Err(_e) => panic!("solution didn't deserialize properly - this should never happen"),
+    Ok(SolutionRecord {
+        solution: from_json(record.solution)?,
+        cohort_id: record.cohort_id,
+        nuid: record.nuid,
+        challenge: from_json(record.cohort_challenge)?,
+        submission_deadline: record.submission_deadline,
+    })
+}
+
+// Everything grading needs to know, read while the applicant's row is locked
+pub struct GradingContext {
+    pub solution: Vec<String>,
+    pub challenge: ChallengeSettings,
+    pub deadline: Option<DateTime<Utc>>,
+    pub extension: (i64, i64),
+    pub attempts: i64,
+}
+
+// What the grader made of a submission. Turned away ones don't get recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GradeOutcome {
+    Graded { ok: bool, late: bool },
+    OutOfAttempts { max_attempts: i64 },
+}
+
+// Decides whether a submission is correct and whether it's late, or turns it away
+pub type Grader<'a> = &'a (dyn Fn(&GradingContext) -> GradeOutcome + Send + Sync);
+
+// Grades and records a submission in one go. The applicant's row is locked until the
+// submission is written, so concurrent submissions take turns instead of all sneaking
+// under the attempt limit together
+#[instrument(skip_all)]
+pub async fn grade_submission_db(
+    pool: &PgPool,
+    token: Uuid,
+    submission_time: DateTime<Utc>,
+    grade: Grader<'_>,
+) -> Result<GradeOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let applicant = query!(
+        r#"SELECT applicants.cohort_id, applicants.nuid, solution,
+        cohorts.challenge AS cohort_challenge, submission_deadline FROM applicants
+        JOIN cohorts ON cohorts.cohort_id = applicants.cohort_id WHERE token=$1
+        FOR UPDATE OF applicants;"#,
+        token
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let extension = query!(
+        r#"SELECT COALESCE(SUM(extra_seconds), 0)::bigint AS "extra_seconds!",
+        COALESCE(SUM(extra_attempts), 0)::bigint AS "extra_attempts!" FROM extensions
+        WHERE cohort_id=$1 AND nuid=$2"#,
+        applicant.cohort_id,
+        applicant.nuid
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let attempts = query!(
+        r#"SELECT COUNT(*) AS "attempts!" FROM submissions WHERE cohort_id=$1 AND nuid=$2"#,
+        applicant.cohort_id,
+        applicant.nuid
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let graded = grade(&GradingContext {
+        solution: from_json(applicant.solution)?,
+        challenge: from_json(applicant.cohort_challenge)?,
+        deadline: applicant.submission_deadline,
+        extension: (extension.extra_seconds, extension.extra_attempts),
+        attempts: attempts.attempts,
+    });
+
+    if let GradeOutcome::Graded { ok, late } = graded {
+        query!(
+            r#"INSERT INTO submissions (cohort_id, nuid, ok, submission_time, late)
+            VALUES ($1, $2, $3, $4, $5);"#,
+            applicant.cohort_id,
+            applicant.nuid,
+            ok,
+            submission_time,
+            late,
+        )
+        .execute(&mut *tx)
+        .await?;
     }
+
+    tx.commit().await?;
+
+    Ok(graded)
+}
+
+// Picks out one applicant for the admin tools - their most recent cohort unless one is
+// named. Returns the cohort's id, name and challenge config
+#[instrument(skip_all)]
+pub async fn find_applicant_db(
+    pool: &PgPool,
+    nuid: &str,
+    cohort: Option<&str>,
+) -> Result<(i32, String, ChallengeSettings), sqlx::Error> {
+    let record = query!(
+        r#"SELECT cohort_id, cohort_name, cohorts.challenge AS cohort_challenge FROM applicants
+        JOIN cohorts using(cohort_id) WHERE nuid=$1 AND ($2::varchar IS NULL OR cohort_name=$2)
+        ORDER BY registration_time DESC LIMIT 1;"#,
+        nuid,
+        cohort
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((
+        record.cohort_id,
+        record.cohort_name,
+        from_json(record.cohort_challenge)?,
+    ))
+}
+
+pub struct ApplicantSummary {
+    pub nuid: String,
+    pub applicant_name: String,
+    pub cohort_name: String,
+    pub registration_time: DateTime<Utc>,
+    pub attempts: i64,
+    pub first_success: Option<DateTime<Utc>>,
+    pub late: bool,
+    pub extra_seconds: i64,
+    pub extra_attempts: i64,
+}
+
+// Everyone who registered, submissions or not. The search term matches against
+// nuids and names
+#[instrument(skip_all)]
+pub async fn list_applicants_db(
+    pool: &PgPool,
+    cohort: Option<&str>,
+    search: Option<&str>,
+) -> Result<Vec<ApplicantSummary>, sqlx::Error> {
+    query_as!(
+        ApplicantSummary,
+        r#"SELECT nuid, applicant_name, cohort_name, registration_time,
+        (SELECT COUNT(*) FROM submissions WHERE submissions.cohort_id=applicants.cohort_id
+        AND submissions.nuid=applicants.nuid) AS "attempts!",
+        (SELECT MIN(submission_time) FROM submissions WHERE
+        submissions.cohort_id=applicants.cohort_id AND submissions.nuid=applicants.nuid
+        AND ok) AS first_success,
+        COALESCE((SELECT late FROM submissions WHERE submissions.cohort_id=applicants.cohort_id
+        AND submissions.nuid=applicants.nuid ORDER BY submission_time DESC LIMIT 1), false)
+        AS "late!",
+        COALESCE((SELECT SUM(extra_seconds) FROM extensions WHERE
+        extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)::bigint
+        AS "extra_seconds!",
+        COALESCE((SELECT SUM(extra_attempts) FROM extensions WHERE
+        extensions.cohort_id=applicants.cohort_id AND extensions.nuid=applicants.nuid), 0)::bigint
+        AS "extra_attempts!"
+        FROM applicants JOIN cohorts ON cohorts.cohort_id=applicants.cohort_id
+        WHERE ($1::varchar IS NULL OR cohort_name=$1)
+        AND ($2::varchar IS NULL OR nuid ILIKE '%' || $2 || '%'
+        OR applicant_name ILIKE '%' || $2 || '%')
+        ORDER BY applicants.cohort_id, nuid;"#,
+        cohort,
+        search
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// A row for audit_log. Admin actions that change something take one and write it in the
+// same transaction, so there's never a change without its row or a row without its change
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    pub actor: String,
+    pub action: String,
+    pub detail: serde_json::Value,
+    pub performed_at: DateTime<Utc>,
+}
+
+// Wipes the slate clean so the applicant gets all of their attempts back
+#[instrument(skip_all)]
+pub async fn reset_submissions_db(
+    pool: &PgPool,
+    cohort_id: i32,
+    nuid: &str,
+    audit: &AuditEntry,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = query!(
+        r#"DELETE FROM submissions WHERE cohort_id=$1 AND nuid=$2;"#,
+        cohort_id,
+        nuid
+    )
+    .execute(&mut *tx)
+    .await?;
+    write_audit_log_db(&mut *tx, audit).await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected())
+}
+
+#[instrument(skip_all)]
+pub async fn replace_challenge_db(
+    pool: &PgPool,
+    cohort_id: i32,
+    nuid: &str,
+    challenge: &[String],
+    solution: &[String],
+    audit: &AuditEntry,
+) -> Result<(), sqlx::Error> {
+    // Old submissions were graded against the old challenge, so they go too
+    let mut tx = pool.begin().await?;
+
+    query!(
+        r#"DELETE FROM submissions WHERE cohort_id=$1 AND nuid=$2;"#,
+        cohort_id,
+        nuid
+    )
+    .execute(&mut *tx)
+    .await?;
+    query!(
+        r#"UPDATE applicants SET challenge=$3, solution=$4 WHERE cohort_id=$1 AND nuid=$2;"#,
+        cohort_id,
+        nuid,
+        Json(challenge) as _,
+        Json(solution) as _,
+    )
+    .execute(&mut *tx)
+    .await?;
+    write_audit_log_db(&mut *tx, audit).await?;
+
+    tx.commit().await
 }
 
-pub async fn write_submission(pool: PgPool, nuid: String, ok: bool) -> Result<(), sqlx::Error> {
-    let submission_time: DateTime<Utc> = SystemTime::now().into();
+#[instrument(skip_all)]
+pub async fn replace_token_db(
+    pool: &PgPool,
+    cohort_id: i32,
+    nuid: &str,
+    token: Uuid,
+    audit: &AuditEntry,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
 
     query!(
-        r#"INSERT INTO submissions (nuid, ok, submission_time) VALUES ($1, $2, $3);"#,
+        r#"UPDATE applicants SET token=$3 WHERE cohort_id=$1 AND nuid=$2;"#,
+        cohort_id,
         nuid,
-        ok,
-        submission_time,
+        token,
+    )
+    .execute(&mut *tx)
+    .await?;
+    write_audit_log_db(&mut *tx, audit).await?;
+
+    tx.commit().await
+}
+
+#[instrument(skip_all)]
+pub async fn delete_applicant_db(
+    pool: &PgPool,
+    cohort_id: i32,
+    nuid: &str,
+    audit: &AuditEntry,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    query!(
+        r#"DELETE FROM extensions WHERE cohort_id=$1 AND nuid=$2;"#,
+        cohort_id,
+        nuid
+    )
+    .execute(&mut *tx)
+    .await?;
+    query!(
+        r#"DELETE FROM submissions WHERE cohort_id=$1 AND nuid=$2;"#,
+        cohort_id,
+        nuid
+    )
+    .execute(&mut *tx)
+    .await?;
+    query!(
+        r#"DELETE FROM applicants WHERE cohort_id=$1 AND nuid=$2;"#,
+        cohort_id,
+        nuid
+    )
+    .execute(&mut *tx)
+    .await?;
+    write_audit_log_db(&mut *tx, audit).await?;
+
+    tx.commit().await
+}
+
+#[instrument(skip_all)]
+pub async fn create_api_key_db(
+    pool: &PgPool,
+    name: &str,
+    key_hash: &str,
+    created_at: DateTime<Utc>,
+    audit: &AuditEntry,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    query!(
+        r#"INSERT INTO api_keys (key_name, key_hash, created_at) VALUES ($1, $2, $3);"#,
+        name,
+        key_hash,
+        created_at,
+    )
+    .execute(&mut *tx)
+    .await?;
+    write_audit_log_db(&mut *tx, audit).await?;
+
+    tx.commit().await
+}
+
+// Nothing gets audited when there was no key to revoke, that's left to the caller
+#[instrument(skip_all)]
+pub async fn revoke_api_key_db(
+    pool: &PgPool,
+    name: &str,
+    revoked_at: DateTime<Utc>,
+    audit: &AuditEntry,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = query!(
+        r#"UPDATE api_keys SET revoked_at=$2 WHERE key_name=$1 AND revoked_at IS NULL;"#,
+        name,
+        revoked_at,
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(0);
+    }
+    write_audit_log_db(&mut *tx, audit).await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected())
+}
+
+#[instrument(skip_all)]
+pub async fn api_key_exists_db(pool: &PgPool, key_hash: &str) -> Result<bool, sqlx::Error> {
+    let record = query!(
+        r#"SELECT EXISTS(SELECT 1 FROM api_keys WHERE key_hash=$1 AND revoked_at IS NULL)
+        AS "exists!";"#,
+        key_hash
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(record.exists)
+}
+
+// Takes the pool for actions that don't change anything, or a transaction for ones that do
+#[instrument(skip_all)]
+pub async fn write_audit_log_db<'e>(
+    executor: impl PgExecutor<'e>,
+    audit: &AuditEntry,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO audit_log (actor, action, detail, performed_at) VALUES ($1, $2, $3, $4);"#,
+        audit.actor,
+        audit.action,
+        audit.detail,
+        audit.performed_at,
     )
-    .execute(&pool)
+    .execute(executor)
     .await?;
 
     Ok(())
@@ -1,9 +1,11 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use warp::reject;
 
 use crate::model::types::Applicant;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub enum ApiError {
     DuplicateUser,
     IncorrectSolution {
@@ -15,9 +17,22 @@ pub enum ApiError {
         applicants_not_found: Vec<String>,
     },
     NoUserFound,
+    NoAttemptsRemaining {
+        max_attempts: i64,
+    },
+    RegistrationNotOpen {
+        opens_at: DateTime<Utc>,
+    },
+    RegistrationClosed {
+        closed_at: Option<DateTime<Utc>>,
+    },
+    InvalidLogFilter {
+        reason: String,
+    },
 }
 
-#[derive(thiserror::Error, Debug, Serialize, Deserialize)]
+// IntoStaticStr is for labelling the rejection counts in /metrics
+#[derive(thiserror::Error, Debug, Serialize, Deserialize, strum::IntoStaticStr)]
 pub enum ModelError {
     #[error("Incorrect solution")]
     IncorrectSolution { given_solution: Vec<String> },
@@ -32,6 +47,22 @@ pub enum ModelError {
     SqlError,
     #[error("No user with this token exists")]
     NoUserFound,
+    #[error("Extensions need a reason and can't take anything away")]
+    InvalidExtension,
+    #[error("Missing or incorrect API key")]
+    Unauthorized,
+    #[error("A reviewer key with this name exists")]
+    DuplicateKey,
+    #[error("No active reviewer key with this name exists")]
+    NoKeyFound,
+    #[error("Registration hasn't opened yet")]
+    RegistrationNotOpen { opens_at: DateTime<Utc> },
+    #[error("Registration is closed")]
+    RegistrationClosed { closed_at: Option<DateTime<Utc>> },
+    #[error("All submission attempts have been used")]
+    NoAttemptsRemaining { max_attempts: i64 },
+    #[error("Invalid log filter")]
+    InvalidLogFilter { reason: String },
 }
 
 impl reject::Reject for ModelError {}
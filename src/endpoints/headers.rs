@@ -0,0 +1,80 @@
+use warp::cors::Cors;
+use warp::http::header::{
+    HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_CONTENT_TYPE_OPTIONS,
+};
+use warp::http::Method;
+use warp::reply::Response;
+use warp::{reject, Filter, Rejection, Reply};
+
+use super::request_id::X_REQUEST_ID;
+use crate::config::{CorsSettings, SecurityHeaders};
+
+// None when there aren't any origins to allow. warp treats an unset origin list as
+// "anyone", which isn't what an empty list in the config means
+pub fn cors(settings: &CorsSettings) -> Option<Cors> {
+    if settings.allowed_origins.is_empty() {
+        return None;
+    }
+    Some(
+        warp::cors()
+            .allow_origins(settings.allowed_origins.iter().map(String::as_str))
+            .allow_methods(settings.allowed_methods.iter().map(String::as_str))
+            .allow_headers(settings.allowed_headers.iter().map(String::as_str))
+            // So the portal can show it when something goes wrong
+            .expose_header(X_REQUEST_ID)
+            .max_age(settings.max_age_secs as u32)
+            .build(),
+    )
+}
+
+// Passes for anything but a preflight sent from an origin that isn't allowed. warp's
+// Cors turns those away with a 403, but there's nothing to protect by refusing them -
+// they get answered without any CORS headers, and the browser won't let the page read
+// the response. Preflights still go through Cors, which is where the origin gets enforced
+pub fn other_origin(allowed: &[String]) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let allowed = allowed.to_vec();
+    warp::method()
+        .and(warp::header::optional::<String>("origin"))
+        .and_then(move |method: Method, origin: Option<String>| {
+            let other = method != Method::OPTIONS
+                && origin.is_some_and(|origin| !allowed.contains(&origin));
+            async move {
+                if other {
+                    Ok(())
+                } else {
+                    Err(reject::not_found())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Anything left empty in the config doesn't get sent. check-config makes sure the
+// rest are valid header values
+pub fn security_headers(settings: &SecurityHeaders) -> Vec<(HeaderName, HeaderValue)> {
+    [
+        (
+            STRICT_TRANSPORT_SECURITY,
+            &settings.strict_transport_security,
+        ),
+        (X_CONTENT_TYPE_OPTIONS, &settings.content_type_options),
+        (REFERRER_POLICY, &settings.referrer_policy),
+        (CONTENT_SECURITY_POLICY, &settings.content_security_policy),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .filter_map(|(name, value)| Some((name, HeaderValue::from_str(value).ok()?)))
+    .collect()
+}
+
+// Handlers that set one of these themselves (/docs and its CSP) keep theirs
+pub fn secure(reply: impl Reply, headers: &[(HeaderName, HeaderValue)]) -> Response {
+    let mut response = reply.into_response();
+    for (name, value) in headers {
+        if !response.headers().contains_key(name) {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
@@ -0,0 +1,44 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, Service};
+use hyper::{Body, Request, Response};
+
+use super::request_id::RequestIds;
+
+// Plain http, what warp::serve did before the request ids needed hooking in under warp
+pub fn serve<S>(
+    service: RequestIds<S>,
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<(SocketAddr, impl Future<Output = ()>)>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let mut incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)
+        .map_err(io::Error::other)?;
+    incoming.set_nodelay(true);
+    let addr = incoming.local_addr();
+
+    let server = hyper::Server::builder(incoming)
+        .serve(make_service_fn(move |conn: &AddrStream| {
+            let service = service.connection(Some(conn.remote_addr()));
+            async move { Ok::<_, Infallible>(service) }
+        }))
+        .with_graceful_shutdown(shutdown);
+
+    Ok((addr, async move {
+        if let Err(e) = server.await {
+            error!("Server error: {}", e);
+        }
+    }))
+}
@@ -1,31 +1,66 @@
 use serde_derive::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use super::errors;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct RegisterResponse {
     pub token: String,
     pub challenge: Vec<String>,
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct RegisterRequest {
     pub name: String,
     pub nuid: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct HandleForgotTokenResponse {
     pub token: String,
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 
 pub struct GetChallenge {
     pub challenge: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct GrantExtensionRequest {
+    pub nuid: String,
+    // Defaults to the applicant's most recent cohort
+    pub cohort: Option<String>,
+    #[serde(default)]
+    pub extra_minutes: u64,
+    #[serde(default)]
+    pub extra_attempts: i64,
+    pub reason: String,
+}
+
+// Directives are RUST_LOG style and go on top of the filter the server started with,
+// e.g. ["generate_tech_app::db::transactions=debug"]. Reverts after `minutes`
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct LogFilterRequest {
+    pub directives: Vec<String>,
+    #[serde(default = "default_log_filter_minutes")]
+    pub minutes: u64,
+}
+
+fn default_log_filter_minutes() -> u64 {
+    10
+}
+
+// Reviewer queries cover every cohort unless one is named with ?cohort=
 #[derive(Serialize, Deserialize)]
+pub struct CohortQuery {
+    pub cohort: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ErrorResponse<'a> {
     pub msg: &'a str,
     #[serde(flatten)]
     pub error: Option<errors::ApiError>,
+    // Same as the X-Request-Id header, for quoting in bug reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
@@ -0,0 +1,203 @@
+use std::convert::Infallible;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+use super::routes::ROUTES;
+use crate::db::Store;
+
+// Everything here is process wide, same as the logger. Handlers bump the domain
+// counters directly and `instrument` takes care of the per-route ones
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    pub registrations: IntCounter,
+    pub submissions: IntCounterVec,
+    pub rejections: IntCounterVec,
+    db_connections: IntGaugeVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "Requests handled, by route and status",
+            ),
+            &["route", "method", "status"],
+        )
+        .unwrap();
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "How long requests took to handle, by route",
+            ),
+            &["route", "method"],
+        )
+        .unwrap();
+        let registrations =
+            IntCounter::new("registrations_total", "Applicants registered").unwrap();
+        let submissions = IntCounterVec::new(
+            Opts::new(
+                "submissions_total",
+                "Submissions by outcome - passed, passed_late, failed or error",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let rejections = IntCounterVec::new(
+            Opts::new("rejections_total", "Error responses, by what caused them"),
+            &["error"],
+        )
+        .unwrap();
+        let db_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Connections in the db pool - idle, in_use, and the max it can grow to",
+            ),
+            &["state"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        registry.register(Box::new(registrations.clone())).unwrap();
+        registry.register(Box::new(submissions.clone())).unwrap();
+        registry.register(Box::new(rejections.clone())).unwrap();
+        registry.register(Box::new(db_connections.clone())).unwrap();
+
+        Self {
+            registry,
+            requests,
+            latency,
+            registrations,
+            submissions,
+            rejections,
+            db_connections,
+        }
+    }
+
+    // The pool numbers are read fresh on every scrape rather than tracked
+    pub fn render(&self, store: &dyn Store) -> String {
+        if let Some(pool) = store.pool_stats() {
+            self.db_connections
+                .with_label_values(&["idle"])
+                .set(pool.idle as i64);
+            self.db_connections
+                .with_label_values(&["in_use"])
+                .set(pool.size.saturating_sub(pool.idle) as i64);
+            self.db_connections
+                .with_label_values(&["max"])
+                .set(pool.max as i64);
+        }
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+// Labels need to stay low cardinality, so tokens and nuids get swapped for a placeholder
+// and anything that isn't one of our routes gets lumped together. Traces name their
+// spans with it too
+pub(crate) fn route_label(path: &str) -> &'static str {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    ROUTES
+        .iter()
+        .map(|(_, route)| *route)
+        .find(|route| {
+            let parts: Vec<&str> = route.trim_matches('/').split('/').collect();
+            parts.len() == segments.len()
+                && parts
+                    .iter()
+                    .zip(&segments)
+                    .all(|(part, segment)| part.starts_with('{') || part == segment)
+        })
+        .unwrap_or("unmatched")
+}
+
+// The method comes straight from the client, so anything non-standard gets lumped
+// together rather than minting a new label value
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => "other",
+    }
+}
+
+// Wraps the whole api (endpoints::end) and records every request on the way out
+pub fn instrument<F, R>(
+    filter: F,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(filter)
+        .map(
+            |started: Instant, method: Method, path: FullPath, reply: R| {
+                let response = reply.into_response();
+                let route = route_label(path.as_str());
+                let method = method_label(&method);
+                METRICS
+                    .requests
+                    .with_label_values(&[route, method, response.status().as_str()])
+                    .inc();
+                METRICS
+                    .latency
+                    .with_label_values(&[route, method])
+                    .observe(started.elapsed().as_secs_f64());
+                response
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::Method;
+
+    use super::{method_label, route_label};
+
+    #[test]
+    fn test_route_label() {
+        assert_eq!(
+            route_label("/submit/6da18e0a-51b3-41fe-8360-86ff8e82c976"),
+            "/submit/{token}"
+        );
+        assert_eq!(route_label("/health/ready"), "/health/ready");
+        assert_eq!(route_label("/wp-admin/setup.php"), "unmatched");
+    }
+
+    #[test]
+    fn test_method_label() {
+        assert_eq!(method_label(&Method::POST), "POST");
+        assert_eq!(
+            method_label(&Method::from_bytes(b"PROPFIND").unwrap()),
+            "other"
+        );
+    }
+}
@@ -1,6 +1,13 @@
 pub mod errors;
+pub mod headers;
+pub mod http;
 pub mod messages;
+pub mod metrics;
+pub mod openapi;
+pub mod request_id;
 pub mod routes;
 pub mod server;
+pub mod shutdown;
+pub mod tls;
 pub use errors::ApiError;
 pub use server::end;
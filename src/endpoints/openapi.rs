@@ -0,0 +1,198 @@
+use utoipa::OpenApi;
+use warp::{reply, Rejection, Reply};
+
+use super::errors::ApiError;
+use super::messages::{
+    ErrorResponse, GetChallenge, GrantExtensionRequest, HandleForgotTokenResponse,
+    LogFilterRequest, RegisterRequest, RegisterResponse,
+};
+use super::server;
+use crate::model::types::{
+    Applicant, ApplicantStatus, BuildInfo, DatabaseHealth, DurationSchema, Extension,
+    LogFilterStatus, Readiness,
+};
+
+// Every handler wired up in `server::end` needs to be listed here, the tests at the
+// bottom of this file will yell at you if anything in `routes::ROUTES` goes missing
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Generate Tech Application API",
+        description = "Register for the technical challenge, fetch it, and submit your solution"
+    ),
+    paths(
+        server::handle_register,
+        server::handle_forgot_token,
+        server::handle_submit,
+        server::handle_get_status,
+        server::handle_get_challenge,
+        server::health_check,
+        server::handle_live,
+        server::handle_ready,
+        server::handle_metrics,
+        server::handle_get_applicant,
+        server::handle_get_applicants,
+        server::handle_grant_extension,
+        server::handle_get_log_filter,
+        server::handle_set_log_filter,
+        server::handle_reset_log_filter,
+    ),
+    components(schemas(
+        RegisterRequest,
+        RegisterResponse,
+        HandleForgotTokenResponse,
+        GetChallenge,
+        GrantExtensionRequest,
+        LogFilterRequest,
+        LogFilterStatus,
+        ErrorResponse,
+        ApiError,
+        Applicant,
+        ApplicantStatus,
+        Extension,
+        DurationSchema,
+        Readiness,
+        DatabaseHealth,
+        BuildInfo,
+    ))
+)]
+pub struct ApiDoc;
+
+// Redoc pulls the spec from /openapi.json, so this page doesn't need to change when the api does
+const DOCS_PAGE: &str = include_str!("docs.html");
+
+pub async fn handle_openapi() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&ApiDoc::openapi()))
+}
+
+// The api's own CSP doesn't let anything load, Redoc needs its script, inline styles,
+// fonts, the spec and a worker for search. The only script allowed is the pinned bundle
+const DOCS_CSP: &str = "default-src 'none'; \
+    script-src https://cdn.redoc.ly/redoc/v2.1.3/bundles/redoc.standalone.js; \
+    style-src 'unsafe-inline' https://fonts.googleapis.com; font-src https://fonts.gstatic.com; \
+    img-src 'self' data: https://cdn.redoc.ly; connect-src 'self'; worker-src blob:; \
+    frame-ancestors 'none'";
+
+pub async fn handle_docs() -> Result<impl Reply, Rejection> {
+    Ok(reply::with_header(
+        reply::html(DOCS_PAGE),
+        "content-security-policy",
+        DOCS_CSP,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use utoipa::OpenApi;
+    use warp::body::BodyDeserializeError;
+    use warp::reject::MethodNotAllowed;
+
+    use super::super::errors::ModelError;
+    use super::super::routes::ROUTES;
+    use super::super::server;
+    use super::ApiDoc;
+    use crate::config::ApplicationSettings;
+    use crate::db::retry::Prepared;
+    use crate::db::MemoryStore;
+
+    // The spec and its docs page don't document themselves
+    const UNDOCUMENTED: [&str; 2] = ["/openapi.json", "/docs"];
+
+    fn documented_routes() -> Vec<(String, String)> {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        spec["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(|method| (method.to_uppercase(), path.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_every_route_is_documented() {
+        let documented = documented_routes();
+        let registered: Vec<(String, String)> = ROUTES
+            .iter()
+            .filter(|(_, path)| !UNDOCUMENTED.contains(path))
+            .map(|(method, path)| (method.to_string(), path.to_string()))
+            .collect();
+
+        for route in &registered {
+            assert!(
+                documented.contains(route),
+                "{} {} is missing from the OpenAPI spec",
+                route.0,
+                route.1
+            );
+        }
+        for route in &documented {
+            assert!(
+                registered.contains(route),
+                "{} {} is in the OpenAPI spec but not in routes::ROUTES",
+                route.0,
+                route.1
+            );
+        }
+    }
+
+    // Tries every method on every path in ROUTES against the filters `end` mounts, so
+    // a route can't be wired up without going in the registry (and so the spec), or
+    // the other way round. Anything that gets as far as a handler, or a rejection one
+    // of them raised, counts as served
+    #[tokio::test]
+    async fn test_routes_match_what_is_served() {
+        let settings: ApplicationSettings = serde_json::from_value(serde_json::json!({
+            "port": 8080,
+            "host": "localhost",
+            "admin_key": "key",
+        }))
+        .unwrap();
+        let routes = server::routes(
+            Some(Arc::new(MemoryStore::new())),
+            Prepared::new(),
+            &settings,
+        );
+
+        let mut paths: Vec<&str> = ROUTES.iter().map(|(_, path)| *path).collect();
+        paths.dedup();
+        for path in paths {
+            let example = path
+                .replace("{token}", "6da18e0a-51b3-41fe-8360-86ff8e82c976")
+                .replace("{nuid}", "001234567");
+            for method in ["GET", "POST", "PUT", "DELETE", "PATCH"] {
+                let served = match warp::test::request()
+                    .method(method)
+                    .path(&example)
+                    .header("authorization", "Bearer key")
+                    .filter(&routes)
+                    .await
+                {
+                    Ok(_) => true,
+                    Err(rejection) => {
+                        rejection.find::<ModelError>().is_some()
+                            || rejection.find::<BodyDeserializeError>().is_some()
+                            || !(rejection.is_not_found()
+                                || rejection.find::<MethodNotAllowed>().is_some())
+                    }
+                };
+                assert_eq!(
+                    served,
+                    ROUTES.contains(&(method, path)),
+                    "{} {} is {} but {} in routes::ROUTES",
+                    method,
+                    path,
+                    if served { "served" } else { "not served" },
+                    if served { "isn't" } else { "is" }
+                );
+            }
+        }
+    }
+}
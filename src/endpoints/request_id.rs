@@ -0,0 +1,156 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use hyper::service::Service;
+use hyper::{Body, Request, Response};
+use opentelemetry::global;
+use opentelemetry::propagation::Extractor;
+use tracing::field::{display, Empty};
+use tracing::{Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+use warp::http::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+
+use super::metrics::route_label;
+use crate::config::LogFields;
+use crate::telemetry::redact;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+// The id of the request being handled, for anything that doesn't get a span's worth of
+// context - error bodies mostly
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+// Whatever's in front of us (fly, a load balancer, the client) gets to pick the id, as
+// long as it's something sane to put in a log line
+fn incoming(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?;
+    let sane = !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+    sane.then(|| id.to_string())
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+// Sits between hyper and warp, so everything the filters and handlers log (and the db
+// calls they make) lands inside a span carrying the request id, and the id goes back out
+// on the response
+#[derive(Clone)]
+pub struct RequestIds<S> {
+    inner: S,
+    fields: LogFields,
+    remote_addr: Option<SocketAddr>,
+}
+
+impl<S: Clone> RequestIds<S> {
+    pub fn new(inner: S, fields: LogFields) -> Self {
+        Self {
+            inner,
+            fields,
+            remote_addr: None,
+        }
+    }
+
+    // One of these per connection, hyper doesn't hand the peer address to the service
+    pub fn connection(&self, remote_addr: Option<SocketAddr>) -> Self {
+        Self {
+            remote_addr,
+            ..self.clone()
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for RequestIds<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        let id = request
+            .headers()
+            .get(&X_REQUEST_ID)
+            .and_then(incoming)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let header = HeaderValue::from_str(&id).expect("request ids are valid header values");
+        request.headers_mut().insert(X_REQUEST_ID, header.clone());
+
+        let span = info_span!(
+            "request",
+            request_id = %id,
+            method = %request.method(),
+            path = redact::path(request.uri().path()),
+            remote_addr = Empty,
+            user_agent = Empty,
+            "otel.name" = %format!("{} {}", request.method(), route_label(request.uri().path())),
+            "otel.kind" = "server",
+            "otel.status_code" = Empty,
+        );
+        // Joins the caller's trace when they sent a traceparent
+        span.set_parent(global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        }));
+        if self.fields.remote_addr {
+            if let Some(addr) = self.remote_addr {
+                span.record("remote_addr", display(addr));
+            }
+        }
+        if self.fields.user_agent {
+            if let Some(agent) = request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|agent| agent.to_str().ok())
+            {
+                span.record("user_agent", agent);
+            }
+        }
+
+        let started = Instant::now();
+        let inner = &mut self.inner;
+        let response = REQUEST_ID.sync_scope(id.clone(), || span.in_scope(|| inner.call(request)));
+        let handled = async move {
+            let mut response = response.await?;
+            response.headers_mut().insert(X_REQUEST_ID, header);
+            if response.status().is_server_error() {
+                Span::current().record("otel.status_code", "ERROR");
+            }
+            info!(
+                status = response.status().as_u16(),
+                latency_ms = started.elapsed().as_millis() as u64,
+                "Handled request"
+            );
+            Ok(response)
+        };
+        Box::pin(REQUEST_ID.scope(id, handled.instrument(span)))
+    }
+}
@@ -1,9 +1,36 @@
-use sqlx::PgPool;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 use warp::filters::BoxedFilter;
 use warp::{path, Filter, Rejection};
 
-use super::messages::RegisterRequest;
+use super::errors::ModelError;
+use super::messages::{CohortQuery, GrantExtensionRequest, LogFilterRequest, RegisterRequest};
+use crate::db::Store;
+use crate::model::keys::verify_reviewer_key;
+
+// Every route `server::end` mounts, with parameters in braces the way the OpenAPI spec
+// writes them. Metrics and traces get their route labels from here, and the tests in
+// `openapi.rs` hold it to both the spec and what `end` actually serves
+pub const ROUTES: &[(&str, &str)] = &[
+    ("POST", "/register"),
+    ("GET", "/forgot_token/{nuid}"),
+    ("POST", "/submit/{token}"),
+    ("GET", "/challenge/{token}"),
+    ("GET", "/status/{token}"),
+    ("GET", "/health"),
+    ("GET", "/health/live"),
+    ("GET", "/health/ready"),
+    ("GET", "/metrics"),
+    ("GET", "/applicant/{nuid}"),
+    ("GET", "/applicants"),
+    ("POST", "/admin/extensions"),
+    ("GET", "/admin/log_filter"),
+    ("PUT", "/admin/log_filter"),
+    ("DELETE", "/admin/log_filter"),
+    ("GET", "/openapi.json"),
+    ("GET", "/docs"),
+];
 
 pub fn register_route() -> BoxedFilter<(RegisterRequest,)> {
     let register = warp::path!("register");
@@ -21,6 +48,21 @@ pub fn health() -> BoxedFilter<()> {
     warp::get().and(health).boxed()
 }
 
+pub fn health_live_route() -> BoxedFilter<()> {
+    let route = warp::path!("health" / "live");
+    warp::get().and(route).boxed()
+}
+
+pub fn health_ready_route() -> BoxedFilter<()> {
+    let route = warp::path!("health" / "ready");
+    warp::get().and(route).boxed()
+}
+
+pub fn metrics_route() -> BoxedFilter<()> {
+    let route = warp::path!("metrics");
+    warp::get().and(route).boxed()
+}
+
 pub fn submit() -> BoxedFilter<(Uuid, Vec<String>)> {
     let route = warp::path!("submit" / Uuid);
     warp::post().and(route).and(warp::body::json()).boxed()
@@ -32,32 +74,144 @@ pub fn get_challenge_route() -> BoxedFilter<(Uuid,)> {
     warp::get().and(route).boxed()
 }
 
+pub fn status_route() -> BoxedFilter<(Uuid,)> {
+    let route = warp::path!("status" / Uuid);
+
+    warp::get().and(route).boxed()
+}
+
 /*
 This route should return:
    - whether or not the applicant provided the correct solution
    - the time elapsed between registration and the first succesful entry
 */
-pub fn get_applicant_route() -> BoxedFilter<(String,)> {
+pub fn get_applicant_route() -> BoxedFilter<(String, CohortQuery)> {
     let route = path!("applicant" / String);
 
-    warp::get().and(route).boxed()
+    warp::get().and(route).and(warp::query()).boxed()
 }
 
-pub fn get_applicants_route() -> BoxedFilter<(Vec<String>,)> {
+pub fn get_applicants_route() -> BoxedFilter<(Vec<String>, CohortQuery)> {
     let route = path!("applicants");
 
-    warp::get().and(route).and(warp::body::json()).boxed()
+    warp::get()
+        .and(route)
+        .and(warp::body::json())
+        .and(warp::query())
+        .boxed()
+}
+
+pub fn grant_extension_route() -> BoxedFilter<(GrantExtensionRequest,)> {
+    let route = path!("admin" / "extensions");
+
+    warp::post().and(route).and(warp::body::json()).boxed()
+}
+
+pub fn log_filter_route() -> BoxedFilter<()> {
+    let route = path!("admin" / "log_filter");
+
+    warp::get().and(route).boxed()
+}
+
+pub fn set_log_filter_route() -> BoxedFilter<(LogFilterRequest,)> {
+    let route = path!("admin" / "log_filter");
+
+    warp::put().and(route).and(warp::body::json()).boxed()
+}
+
+pub fn reset_log_filter_route() -> BoxedFilter<()> {
+    let route = path!("admin" / "log_filter");
+
+    warp::delete().and(route).boxed()
+}
+
+pub fn openapi_route() -> BoxedFilter<()> {
+    let route = path!("openapi.json");
+
+    warp::get().and(route).boxed()
+}
+
+pub fn docs_route() -> BoxedFilter<()> {
+    let route = path!("docs");
+
+    warp::get().and(route).boxed()
+}
+
+// Constant time, so how long a wrong key takes to turn away doesn't give away how much
+// of it was right
+fn same_key(given: &str, expected: &str) -> bool {
+    given.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+// Doesn't extract anything, just turns away requests without the admin key
+pub fn with_admin(key: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |given: Option<String>| {
+            let key = key.clone();
+            async move {
+                let given = given
+                    .as_deref()
+                    .and_then(|given| given.strip_prefix("Bearer "));
+                match (key, given) {
+                    (Some(key), Some(given)) if same_key(given, &key) => Ok(()),
+                    _ => Err(warp::reject::custom(ModelError::Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Reviewers get in with either the admin key or one of the keys made with the admin cli.
+// Without a pepper (require_reviewer_key is off) everyone gets in
+pub fn with_reviewer(
+    o: Option<Arc<dyn Store>>,
+    admin_key: Option<String>,
+    pepper: Option<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |given: Option<String>| {
+            let o = o.clone();
+            let admin_key = admin_key.clone();
+            let pepper = pepper.clone();
+            async move {
+                let pepper = match pepper {
+                    Some(pepper) => pepper,
+                    None => return Ok(()),
+                };
+                let given = match given
+                    .as_deref()
+                    .and_then(|given| given.strip_prefix("Bearer "))
+                {
+                    Some(given) => given.to_string(),
+                    None => return Err(warp::reject::custom(ModelError::Unauthorized)),
+                };
+                if admin_key.is_some_and(|key| same_key(&given, &key)) {
+                    return Ok(());
+                }
+                let store = match o {
+                    Some(store) => store,
+                    None => return Err(warp::reject::not_found()),
+                };
+                match verify_reviewer_key(store.as_ref(), &pepper, &given).await {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(warp::reject::custom(ModelError::Unauthorized)),
+                    Err(e) => Err(warp::reject::custom(e)),
+                }
+            }
+        })
+        .untuple_one()
 }
 
-// All this does is include the db pool in scope, it shouldn't change the actual route
-pub fn with_db(o: Option<PgPool>) -> impl Filter<Extract = (PgPool,), Error = Rejection> + Clone {
+// All this does is include the store in scope, it shouldn't change the actual route
+pub fn with_db(
+    o: Option<Arc<dyn Store>>,
+) -> impl Filter<Extract = (Arc<dyn Store>,), Error = Rejection> + Clone {
     warp::any().and_then(move || {
-        // This is is fine b/c a PgPool is just a reference counted
-        // pointer to an inner db. The implementation uses Arc
+        // Cloning the Arc is cheap, and a PgPool underneath is reference counted too
         let o = o.clone();
         async move {
-            if let Some(pool) = o {
-                Ok(pool)
+            if let Some(store) = o {
+                Ok(store)
             } else {
                 Err(warp::reject::not_found())
             }
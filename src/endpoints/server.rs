@@ -1,23 +1,38 @@
 use std::convert::Infallible;
 
 use super::errors::ModelError;
+use super::headers;
 use super::messages::{
-    ErrorResponse, GetChallenge, HandleForgotTokenResponse, RegisterRequest, RegisterResponse,
+    CohortQuery, ErrorResponse, GetChallenge, GrantExtensionRequest, HandleForgotTokenResponse,
+    LogFilterRequest, RegisterRequest, RegisterResponse,
 };
+use super::metrics::METRICS;
+use super::openapi::{handle_docs, handle_openapi};
 use super::routes::{
-    forgot_token_route, get_applicant_route, get_applicants_route, get_challenge_route, health,
-    register_route, submit, with_db,
+    docs_route, forgot_token_route, get_applicant_route, get_applicants_route, get_challenge_route,
+    grant_extension_route, health, health_live_route, health_ready_route, log_filter_route,
+    metrics_route, openapi_route, register_route, reset_log_filter_route, set_log_filter_route,
+    status_route, submit, with_admin, with_db, with_reviewer,
 };
+use crate::config::ApplicationSettings;
+use crate::db::retry::Prepared;
+use crate::db::Store;
 use crate::endpoints::ApiError;
 use crate::model::{
-    check_solution, get_applicants, register_user, retreive_challenge, retreive_token,
+    check_solution, get_applicants, get_status, grant_extension, readiness, register_user,
+    retreive_challenge, retreive_token,
 };
+use crate::telemetry::filter::LogFilter;
+use crate::telemetry::{self, redact};
 use serde_json::json;
-use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::instrument;
 use uuid::Uuid;
 use warp::body::BodyDeserializeError;
+use warp::cors::CorsForbidden;
 use warp::hyper::StatusCode;
-use warp::reject::MethodNotAllowed;
+use warp::reject::{InvalidQuery, MethodNotAllowed};
 use warp::{reject, reply, Filter, Rejection, Reply};
 
 /*
@@ -40,88 +55,191 @@ macro_rules! api_err {
         $crate::endpoints::messages::ErrorResponse {
             msg: $msg,
             error: Some($api_err),
+            request_id: $crate::endpoints::request_id::current(),
         }
     };
     ($msg:expr) => {
         $crate::endpoints::messages::ErrorResponse {
             msg: $msg,
             error: None,
+            request_id: $crate::endpoints::request_id::current(),
         }
     };
 }
 
-pub fn end(o: Option<PgPool>) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
+pub fn end(
+    o: Option<Arc<dyn Store>>,
+    prepared: Prepared,
+    settings: ApplicationSettings,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    let api = routes(o, prepared, &settings)
+        .recover(handle_rejection)
+        .map(Reply::into_response)
+        .boxed();
+
+    // CORS goes around the recovered api so error responses get its headers too, and
+    // browsers can read them
+    let api = match headers::cors(&settings.cors) {
+        Some(cors) => headers::other_origin(&settings.cors.allowed_origins)
+            .and(api.clone())
+            .or(api.with(cors).map(Reply::into_response))
+            .unify()
+            .boxed(),
+        None => api,
+    };
+    let security = headers::security_headers(&settings.headers);
+    // The only rejection left out here is a preflight from an origin CORS turned away
+    api.recover(handle_rejection)
+        .map(move |reply| headers::secure(reply, &security))
+}
+
+// Every route in `routes::ROUTES`, before rejections get turned into responses
+pub(crate) fn routes(
+    o: Option<Arc<dyn Store>>,
+    prepared: Prepared,
+    settings: &ApplicationSettings,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // The filters compare against the raw values, so this is as far as the Secrets go
+    let admin_key = settings.admin_key.as_ref().map(|key| key.expose().clone());
+    let pepper = settings
+        .require_reviewer_key
+        .then(|| settings.api_key_pepper.expose().clone());
     handle_with_db!(register_route, o, handle_register)
         .or(handle_with_db!(forgot_token_route, o, handle_forgot_token))
         .or(handle_with_db!(submit, o, handle_submit))
+        .or(handle_with_db!(status_route, o, handle_get_status))
         .or(handle_with_db!(
             get_challenge_route,
             o,
             handle_get_challenge
         ))
         .or(health().and_then(health_check))
-        .or(handle_with_db!(
-            get_applicant_route,
-            o,
-            handle_get_applicant
-        ))
-        .or(handle_with_db!(
-            get_applicants_route,
-            o,
-            handle_get_applicants
-        ))
-        .recover(handle_rejection)
+        .or(health_live_route().and_then(handle_live))
+        .or(health_ready_route()
+            .and(with_db(o.clone()))
+            .and(warp::any().map(move || prepared.clone()))
+            .and_then(handle_ready))
+        .or(metrics_route()
+            .and(with_admin(admin_key.clone()))
+            .and(with_db(o.clone()))
+            .and_then(handle_metrics))
+        .or(get_applicant_route()
+            .and(with_reviewer(o.clone(), admin_key.clone(), pepper.clone()))
+            .and(with_db(o.clone()))
+            .and_then(handle_get_applicant))
+        .or(get_applicants_route()
+            .and(with_reviewer(o.clone(), admin_key.clone(), pepper.clone()))
+            .and(with_db(o.clone()))
+            .and_then(handle_get_applicants))
+        .or(grant_extension_route()
+            .and(with_admin(admin_key.clone()))
+            .and(with_db(o.clone()))
+            .and_then(handle_grant_extension))
+        .or(log_filter_route()
+            .and(with_admin(admin_key.clone()))
+            .and_then(handle_get_log_filter))
+        .or(set_log_filter_route()
+            .and(with_admin(admin_key.clone()))
+            .and_then(handle_set_log_filter))
+        .or(reset_log_filter_route()
+            .and(with_admin(admin_key.clone()))
+            .and_then(handle_reset_log_filter))
+        .or(openapi_route().and_then(handle_openapi))
+        .or(docs_route().and_then(handle_docs))
 }
 
 // This is weird - if I use the WarpResult alias here, it forces me to use the same
 // concrete return types as the other functions that use the WarpResult alias
 // def because using `impl trait` syntax in aliases is experimental and on nightly
 // should switch away from nightly - it'll make deployment more stable as well
-pub async fn handle_get_applicant(nuid: String, p: PgPool) -> Result<impl Reply, Rejection> {
+#[utoipa::path(
+    get,
+    path = "/applicant/{nuid}",
+    params(
+        ("nuid" = String, Path, description = "NUID the applicant registered with"),
+        ("cohort" = Option<String>, Query, description = "Only look in this cohort"),
+        ("Authorization" = Option<String>, Header, description = "Bearer <reviewer or admin key>, only needed when require_reviewer_key is on"),
+    ),
+    responses(
+        (status = 200, description = "The applicant's latest submission, from their most recent cohort", body = Applicant),
+        (status = 401, description = "Missing or incorrect reviewer key, when require_reviewer_key is on", body = ErrorResponse),
+        (status = 404, description = "No submissions from this applicant", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_get_applicant(
+    nuid: String,
+    query: CohortQuery,
+    store: Arc<dyn Store>,
+) -> Result<impl Reply, Rejection> {
     // look up the applicant
-    info!("Fetching applicant: {}", nuid);
-    match get_applicants(p, &[nuid.clone()]).await {
-        Ok(applicant) => {
-            let code;
-            if applicant.len() == 1 {
-                code = StatusCode::OK;
-                Ok(reply::with_status(reply::json(&applicant[0]), code))
-            } else if applicant.is_empty() {
-                Err(reject::custom(ModelError::ApplicantsNotFound {
-                    applicants_found: vec![],
-                    applicants_not_found: vec![nuid],
-                }))
-            } else {
-                let code = StatusCode::INTERNAL_SERVER_ERROR;
-                let msg = api_err!("Fetched the wrong number of applicants somehow ¯\\_(ツ)_/¯  ");
-                Ok(reply::with_status(reply::json(&msg), code))
-            }
-        }
+    info!(nuid = %redact::nuid(&nuid), "Fetching applicant");
+    match get_applicants(
+        store.as_ref(),
+        std::slice::from_ref(&nuid),
+        query.cohort.as_deref(),
+    )
+    .await
+    {
+        // Rows come back in the order the cohorts opened, and reviewers care about the latest
+        Ok(mut applicant) => match applicant.pop() {
+            Some(applicant) => Ok(reply::json(&applicant)),
+            None => Err(reject::custom(ModelError::ApplicantsNotFound {
+                applicants_found: vec![],
+                applicants_not_found: vec![nuid],
+            })),
+        },
         // This will just bubble down to a 500 which seems super reasonable
         // Assuming that this is a sql error - no other reason that this would fail
         Err(e) => {
-            error!("Something went wrong fetching the applicant: {:?}", e);
+            error!("Something went wrong fetching the applicant: {}", e);
             Err(reject::custom(e))
         }
     }
 }
 
-pub async fn handle_get_applicants(nuids: Vec<String>, p: PgPool) -> Result<impl Reply, Rejection> {
-    info!("Fetching applicants: {:#?}", nuids);
-    match get_applicants(p, &nuids).await {
+#[utoipa::path(
+    get,
+    path = "/applicants",
+    params(
+        ("cohort" = Option<String>, Query, description = "Only look in this cohort"),
+        ("Authorization" = Option<String>, Header, description = "Bearer <reviewer or admin key>, only needed when require_reviewer_key is on"),
+    ),
+    request_body = Vec<String>,
+    responses(
+        (status = 200, description = "The latest submission from every applicant requested, once per cohort they applied to", body = [Applicant]),
+        (status = 401, description = "Missing or incorrect reviewer key, when require_reviewer_key is on", body = ErrorResponse),
+        (status = 404, description = "Some of the applicants requested have no submissions", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_get_applicants(
+    nuids: Vec<String>,
+    query: CohortQuery,
+    store: Arc<dyn Store>,
+) -> Result<impl Reply, Rejection> {
+    info!(
+        count = nuids.len(),
+        nuids = %redact::nuids(&nuids),
+        "Fetching applicants"
+    );
+    match get_applicants(store.as_ref(), &nuids, query.cohort.as_deref()).await {
         Ok(applicants) => {
-            if applicants.len() == nuids.len() {
+            let mut applicants_not_found: Vec<String> = nuids.clone();
+            // ok so basically we copy the nuids to a new list,
+            // then keep only the nuids that do not correspond to any of the
+            // applicants we fetched. This means that we only get the applicants
+            // that aren't found. Someone can show up once per cohort, so
+            // comparing lengths doesn't cut it anymore
+            // worst case O((n/2)^2) - polynomial so we're fine
+            applicants_not_found
+                .retain(|nuid| !applicants.iter().any(|applicant| applicant.nuid == *nuid));
+
+            if applicants_not_found.is_empty() {
                 Ok(reply::json(&applicants))
             } else {
-                let mut applicants_not_found: Vec<String> = nuids.clone();
-                // ok so basically we copy the nuids to a new list,
-                // then keep only the nuids that do not correspond to any of the
-                // applicants we fetched. This means that we only get the applicants
-                // that aren't found
-                // worst case O((n/2)^2) - polynomial so we're fine
-                applicants_not_found
-                    .retain(|nuid| !applicants.iter().any(|applicant| applicant.nuid == *nuid));
-
                 Err(reject::custom(ModelError::ApplicantsNotFound {
                     applicants_found: applicants,
                     applicants_not_found,
@@ -129,45 +247,92 @@ pub async fn handle_get_applicants(nuids: Vec<String>, p: PgPool) -> Result<impl
             }
         }
         Err(e) => {
-            error!("Something went wrong fetching the applicants: {:?}", e);
+            error!("Something went wrong fetching the applicants: {}", e);
             Err(reject::custom(e))
         }
     }
 }
 
-pub async fn handle_register(request: RegisterRequest, p: PgPool) -> Result<impl Reply, Rejection> {
+#[utoipa::path(
+    post,
+    path = "/register",
+    request_body = RegisterRequest,
+    responses(
+        (status = 200, description = "Registered, here's your token and challenge", body = RegisterResponse),
+        (status = 400, description = "Malformed request body", body = ErrorResponse),
+        (status = 403, description = "Registration hasn't opened yet", body = ErrorResponse),
+        (status = 409, description = "This NUID has already registered", body = ErrorResponse),
+        (status = 410, description = "Registration has closed", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_register(
+    request: RegisterRequest,
+    store: Arc<dyn Store>,
+) -> Result<impl Reply, Rejection> {
     info!(
-        "registering user {}, with nuid {}",
-        request.name, request.nuid
+        nuid = %redact::nuid(&request.nuid),
+        name = redact::name(&request.name),
+        "Registering applicant"
     );
 
-    match register_user(p, request.name, request.nuid).await {
-        Ok((token, challenge)) => Ok(reply::json(&RegisterResponse {
-            token: token.to_string(),
-            challenge,
-        })),
+    match register_user(store.as_ref(), request.name, request.nuid).await {
+        Ok((token, challenge)) => {
+            METRICS.registrations.inc();
+            Ok(reply::json(&RegisterResponse {
+                token: token.to_string(),
+                challenge,
+            }))
+        }
         // Should be a 409 conflict error if the error doesnt exist,
         Err(e) => {
-            error!("Something went wrong registering the user: {:?}", e);
+            error!("Something went wrong registering the user: {}", e);
             Err(reject::custom(e))
         }
     }
 }
 
 // On error, send back a 400
+#[utoipa::path(
+    post,
+    path = "/submit/{token}",
+    params(("token" = Uuid, Path, description = "Token handed out at registration")),
+    request_body = Vec<String>,
+    responses(
+        (status = 200, description = "Correct solution. Submissions after the deadline are still graded, but marked as late", body = String),
+        (status = 400, description = "Incorrect solution or malformed body", body = ErrorResponse),
+        (status = 403, description = "Every submission attempt has been used", body = ErrorResponse),
+        (status = 404, description = "No applicant with this token", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
 pub async fn handle_submit(
     token: Uuid,
     soln: Vec<String>,
-    p: PgPool,
+    store: Arc<dyn Store>,
 ) -> Result<impl Reply, Rejection> {
     info!(
-        "Receiving submission from user with token: {:?}\nsubmission: {:#?}",
-        token, soln
+        token = %redact::token(&token),
+        submission = %redact::payload(&soln),
+        "Receiving submission"
     );
     // Depending on what check solution does, either return a reply json or a rejection
-    match check_solution(p, token, &soln).await {
-        Ok(is_correct) => {
-            if is_correct {
+    let result = check_solution(store.as_ref(), token, &soln).await;
+    let outcome = match result {
+        Ok((true, true)) => "passed_late",
+        Ok((true, false)) => "passed",
+        Ok((false, _)) => "failed",
+        Err(_) => "error",
+    };
+    METRICS.submissions.with_label_values(&[outcome]).inc();
+    match result {
+        Ok((is_correct, late)) => {
+            if is_correct && late {
+                Ok(reply::json(
+                    &"Correct! Nice work - this came in after the deadline though, so it's been marked as late"
+                        .to_string(),
+                ))
+            } else if is_correct {
                 Ok(reply::json(&"Correct! Nice work".to_string()))
             } else {
                 Err(reject::custom(ModelError::IncorrectSolution {
@@ -176,50 +341,277 @@ pub async fn handle_submit(
             }
         }
         Err(e) => {
-            error!("Submission Failed: {:?}", e);
+            error!("Submission failed: {}", e);
             Err(reject::custom(e))
         }
     }
 }
 
-pub async fn handle_forgot_token(nuid: String, p: PgPool) -> Result<impl Reply, Rejection> {
-    info!("Fetching token for user: {}", nuid);
-    match retreive_token(p, &nuid).await {
+#[utoipa::path(
+    get,
+    path = "/status/{token}",
+    params(("token" = Uuid, Path, description = "Token handed out at registration")),
+    responses(
+        (status = 200, description = "Where the applicant stands on the challenge", body = ApplicantStatus),
+        (status = 404, description = "No applicant with this token", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_get_status(
+    token: Uuid,
+    store: Arc<dyn Store>,
+) -> Result<impl Reply, Rejection> {
+    info!(token = %redact::token(&token), "Fetching status");
+    match get_status(store.as_ref(), token).await {
+        Ok(status) => Ok(reply::json(&status)),
+        Err(e) => {
+            error!("Fetching status failed: {}", e);
+            Err(reject::custom(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/extensions",
+    request_body = GrantExtensionRequest,
+    params(("Authorization" = String, Header, description = "Bearer <admin key>")),
+    responses(
+        (status = 200, description = "The applicant's extension totals after this grant", body = Extension),
+        (status = 400, description = "Missing reason or negative extra attempts", body = ErrorResponse),
+        (status = 401, description = "Missing or incorrect admin key", body = ErrorResponse),
+        (status = 404, description = "No applicant with this NUID in this cohort", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_grant_extension(
+    request: GrantExtensionRequest,
+    store: Arc<dyn Store>,
+) -> Result<impl Reply, Rejection> {
+    info!(
+        nuid = %redact::nuid(&request.nuid),
+        extra_minutes = request.extra_minutes,
+        extra_attempts = request.extra_attempts,
+        reason = %redact::payload(&request.reason),
+        "Granting extension"
+    );
+    match grant_extension(
+        store.as_ref(),
+        &request.nuid,
+        request.cohort.as_deref(),
+        Duration::from_secs(request.extra_minutes.saturating_mul(60)),
+        request.extra_attempts,
+        &request.reason,
+    )
+    .await
+    {
+        Ok(extension) => Ok(reply::json(&extension)),
+        Err(e) => {
+            error!(
+                nuid = %redact::nuid(&request.nuid),
+                "Granting extension failed: {}",
+                e
+            );
+            Err(reject::custom(e))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/forgot_token/{nuid}",
+    params(("nuid" = String, Path, description = "NUID the applicant registered with")),
+    responses(
+        (status = 200, description = "The token for this NUID", body = HandleForgotTokenResponse),
+        (status = 404, description = "No applicant with this NUID", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_forgot_token(
+    nuid: String,
+    store: Arc<dyn Store>,
+) -> Result<impl Reply, Rejection> {
+    info!(nuid = %redact::nuid(&nuid), "Fetching token");
+    match retreive_token(store.as_ref(), &nuid).await {
         Ok(token) => Ok(reply::json(&HandleForgotTokenResponse {
             token: token.to_string(),
         })),
         Err(e) => {
-            error!("Fetching token failed for user {}: {:?}", nuid, e);
+            error!(nuid = %redact::nuid(&nuid), "Fetching token failed: {}", e);
             Err(reject::custom(e))
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses((status = 200, description = "The server is up", body = Object, example = json!({"healthy": true})))
+)]
+#[instrument(skip_all)]
 pub async fn health_check() -> Result<impl Reply, Rejection> {
     Ok(reply::json(&json!({
         "healthy": true
     })))
 }
 
-pub async fn handle_get_challenge(token: Uuid, pool: PgPool) -> Result<impl Reply, Rejection> {
-    info!("Fetching challenge string for user with token: {}", token);
-    match retreive_challenge(&pool, token).await {
+// Liveness only says the process is answering, it never touches the db - restarting
+// because the db is down wouldn't help anything
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses((status = 200, description = "The process is up", body = Object, example = json!({"live": true})))
+)]
+#[instrument(skip_all)]
+pub async fn handle_live() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&json!({
+        "live": true
+    })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    params(("Authorization" = String, Header, description = "Bearer <admin key>")),
+    responses(
+        (status = 200, description = "Request, registration, submission, error and db pool numbers in Prometheus' text format", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or incorrect admin key", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_metrics(store: Arc<dyn Store>) -> Result<impl Reply, Rejection> {
+    Ok(reply::with_header(
+        METRICS.render(store.as_ref()),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+// How long readiness waits on the db before calling it down
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "The db answered, send traffic here", body = Readiness),
+        (status = 503, description = "The db didn't answer in time, or isn't migrated and synced yet", body = Readiness),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_ready(
+    store: Arc<dyn Store>,
+    prepared: Prepared,
+) -> Result<impl Reply, Rejection> {
+    let report = readiness(store.as_ref(), prepared.get(), READY_TIMEOUT).await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(reply::with_status(reply::json(&report), status))
+}
+
+#[utoipa::path(
+    get,
+    path = "/challenge/{token}",
+    params(("token" = Uuid, Path, description = "Token handed out at registration")),
+    responses(
+        (status = 200, description = "The applicant's challenge strings", body = GetChallenge),
+        (status = 404, description = "No applicant with this token", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_get_challenge(
+    token: Uuid,
+    store: Arc<dyn Store>,
+) -> Result<impl Reply, Rejection> {
+    info!(token = %redact::token(&token), "Fetching challenge");
+    match retreive_challenge(store.as_ref(), token).await {
         Ok(challenge) => {
-            info!("Challenge strings: {:?}", challenge);
+            debug!(challenge = %redact::payload(&challenge), "Found challenge");
             Ok(reply::json(&GetChallenge { challenge }))
         }
         Err(e) => {
-            error!("Fetching challenge_string failed {:?}", e);
+            error!("Fetching challenge failed: {}", e);
             Err(reject::custom(e))
         }
     }
 }
 
+// Longest a log filter change can stay in place
+const MAX_LOG_FILTER_MINUTES: u64 = 24 * 60;
+
+fn log_filter() -> Result<&'static LogFilter, Rejection> {
+    telemetry::filter::get().ok_or_else(|| {
+        reject::custom(ModelError::InvalidLogFilter {
+            reason: String::from("logging wasn't set up to be changed at runtime"),
+        })
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/log_filter",
+    params(("Authorization" = String, Header, description = "Bearer <admin key>")),
+    responses(
+        (status = 200, description = "The log filter in place right now, and when it reverts", body = LogFilterStatus),
+        (status = 401, description = "Missing or incorrect admin key", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_get_log_filter() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&log_filter()?.status()))
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/log_filter",
+    request_body = LogFilterRequest,
+    params(("Authorization" = String, Header, description = "Bearer <admin key>")),
+    responses(
+        (status = 200, description = "The new filter, and when it reverts to the one the server started with", body = LogFilterStatus),
+        (status = 400, description = "Bad directives, or minutes isn't between 1 and 1440", body = ErrorResponse),
+        (status = 401, description = "Missing or incorrect admin key", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_set_log_filter(request: LogFilterRequest) -> Result<impl Reply, Rejection> {
+    if !(1..=MAX_LOG_FILTER_MINUTES).contains(&request.minutes) {
+        return Err(reject::custom(ModelError::InvalidLogFilter {
+            reason: format!("minutes has to be between 1 and {}", MAX_LOG_FILTER_MINUTES),
+        }));
+    }
+    match log_filter()?.apply(
+        &request.directives,
+        Duration::from_secs(request.minutes * 60),
+    ) {
+        Ok(status) => Ok(reply::json(&status)),
+        Err(reason) => Err(reject::custom(ModelError::InvalidLogFilter { reason })),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/log_filter",
+    params(("Authorization" = String, Header, description = "Bearer <admin key>")),
+    responses(
+        (status = 200, description = "Back on the filter the server started with", body = LogFilterStatus),
+        (status = 401, description = "Missing or incorrect admin key", body = ErrorResponse),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn handle_reset_log_filter() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&log_filter()?.reset()))
+}
+
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let code;
     let msg: ErrorResponse;
+    let label: &'static str;
 
     if let Some(wrapped_err) = err.find::<ModelError>() {
+        label = wrapped_err.into();
         match wrapped_err {
             ModelError::DuplicateUser => {
                 msg = api_err!("This NUID has already been used to register");
@@ -256,10 +648,73 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
                 code = StatusCode::NOT_FOUND;
                 msg = api_err!("No user with this token or nuid exists")
             }
+            ModelError::InvalidExtension => {
+                code = StatusCode::BAD_REQUEST;
+                msg = api_err!(
+                    "Extensions need a reason, and extra time and attempts can't be negative"
+                )
+            }
+            ModelError::Unauthorized => {
+                code = StatusCode::UNAUTHORIZED;
+                msg = api_err!("This route needs a valid API key")
+            }
+            ModelError::DuplicateKey => {
+                code = StatusCode::CONFLICT;
+                msg = api_err!("A reviewer key with this name already exists")
+            }
+            ModelError::NoKeyFound => {
+                code = StatusCode::NOT_FOUND;
+                msg = api_err!("No active reviewer key with this name exists")
+            }
+            ModelError::RegistrationNotOpen { opens_at } => {
+                code = StatusCode::FORBIDDEN;
+                msg = api_err!(
+                    "Registration hasn't opened yet",
+                    ApiError::RegistrationNotOpen {
+                        opens_at: *opens_at
+                    }
+                )
+            }
+            ModelError::RegistrationClosed { closed_at } => {
+                code = StatusCode::GONE;
+                msg = api_err!(
+                    "Registration is closed",
+                    ApiError::RegistrationClosed {
+                        closed_at: *closed_at
+                    }
+                )
+            }
+            ModelError::InvalidLogFilter { reason } => {
+                code = StatusCode::BAD_REQUEST;
+                msg = api_err!(
+                    "That log filter can't be used",
+                    ApiError::InvalidLogFilter {
+                        reason: reason.clone()
+                    }
+                )
+            }
+            ModelError::NoAttemptsRemaining { max_attempts } => {
+                code = StatusCode::FORBIDDEN;
+                msg = api_err!(
+                    "You've used all of your submission attempts",
+                    ApiError::NoAttemptsRemaining {
+                        max_attempts: *max_attempts
+                    }
+                )
+            }
         }
     } else if err.find::<BodyDeserializeError>().is_some() {
+        label = "BodyDeserializeError";
         code = StatusCode::BAD_REQUEST;
         msg = api_err!("Bad request - check your request body")
+    } else if err.find::<CorsForbidden>().is_some() {
+        label = "CorsForbidden";
+        code = StatusCode::FORBIDDEN;
+        msg = api_err!("Requests from this origin aren't allowed")
+    } else if err.find::<InvalidQuery>().is_some() {
+        label = "InvalidQuery";
+        code = StatusCode::BAD_REQUEST;
+        msg = api_err!("Bad request - check your query string")
     }
     // This is super jank - we're mapping a 405 to a 404
     // This issue explains why: https://github.com/seanmonstar/warp/issues/77
@@ -268,23 +723,609 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     // This shit sucks - for some reason post request are being logged as
     // methodNotAllowed
     else if err.find::<MethodNotAllowed>().is_some() {
+        label = "MethodNotAllowed";
         code = StatusCode::NOT_FOUND;
         msg = api_err!(
             "The path you're trying to hit doesn't exist - check your endpoints and your request method"
         );
     } else {
+        label = "Unhandled";
         code = StatusCode::INTERNAL_SERVER_ERROR;
         msg =
             api_err!("Unhandled rejection - email me at bhat.am@northeastern.edu if this happens");
         warn!("{:?}", err)
     }
 
+    METRICS.rejections.with_label_values(&[label]).inc();
     Ok(reply::with_status(reply::json(&msg), code))
 }
 
+#[cfg(test)]
 mod tests {
-    // We'll write API tests here eventually - i wonder if I can write service tests
-    // using docker compose. Should probably figure out how to do that for generate
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{Duration, Utc};
+    use hyper::service::Service;
+    use hyper::{Body, Request};
+    use opentelemetry::global;
+    use opentelemetry::trace::{TraceId, TracerProvider as _};
+    use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use serde_json::{json, Value};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use tracing_subscriber::prelude::*;
+    use uuid::Uuid;
+    use warp::http::HeaderValue;
+    use warp::hyper::StatusCode;
 
-    // You can just mock the DB, you absolute muppet
+    use super::end;
+    use crate::config::{
+        ApplicationSettings, ChallengeSettings, CohortSettings, CorsSettings, DatabaseSettings,
+        LogFields, Secret, SecurityHeaders,
+    };
+    use crate::db::retry::{self, Prepared};
+    use crate::db::{MemoryStore, PgStore, SqliteStore, Store};
+    use crate::endpoints::errors::ModelError;
+    use crate::endpoints::request_id::{RequestIds, X_REQUEST_ID};
+    use crate::model::keys::{create_reviewer_key, revoke_reviewer_key};
+
+    const ADMIN_KEY: &str = "test-admin-key";
+
+    fn cohort(opens_in: i64, closes_in: Option<i64>) -> CohortSettings {
+        CohortSettings {
+            name: String::from("test"),
+            opens_at: Utc::now() + Duration::days(opens_in),
+            closes_at: closes_in.map(|days| Utc::now() + Duration::days(days)),
+            submission_deadline: None,
+            challenge: ChallengeSettings {
+                size: 10,
+                max_attempts: Some(2),
+                seed: None,
+            },
+        }
+    }
+
+    // No postgres needed, the memory store stands in for it
+    async fn setup(cohort: CohortSettings) -> Arc<dyn Store> {
+        let store = Arc::new(MemoryStore::new());
+        store.sync_cohort(&cohort).await.unwrap();
+        store
+    }
+
+    // What retry::start hands back once the store is migrated and synced
+    fn prepared() -> Prepared {
+        let prepared = Prepared::new();
+        prepared.set();
+        prepared
+    }
+
+    fn settings() -> ApplicationSettings {
+        ApplicationSettings {
+            port: 8080,
+            host: String::from("localhost"),
+            bind: [127, 0, 0, 1].into(),
+            drain_secs: 0,
+            kill_timeout_secs: 5,
+            tls: None,
+            admin_key: Some(Secret::new(String::from(ADMIN_KEY))),
+            require_reviewer_key: false,
+            api_key_pepper: Secret::default(),
+            cors: CorsSettings::default(),
+            headers: SecurityHeaders::default(),
+        }
+    }
+
+    async fn register(store: &Arc<dyn Store>, nuid: &str) -> (StatusCode, Value) {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/register")
+            .json(&json!({"name": "Test Applicant", "nuid": nuid}))
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        (res.status(), serde_json::from_slice(res.body()).unwrap())
+    }
+
+    async fn submit(store: &Arc<dyn Store>, token: &str, soln: &Vec<String>) -> StatusCode {
+        warp::test::request()
+            .method("POST")
+            .path(&format!("/submit/{}", token))
+            .json(soln)
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_register_and_submit() {
+        register_and_submit(setup(cohort(-1, None)).await).await;
+    }
+
+    // Same thing against sqlite, mostly to make sure its queries hold up
+    #[tokio::test]
+    async fn test_register_and_submit_sqlite() {
+        let store = SqliteStore::connect("sqlite::memory:").await.unwrap();
+        store.migrate().await.unwrap();
+        store.sync_cohort(&cohort(-1, None)).await.unwrap();
+        register_and_submit(Arc::new(store)).await;
+    }
+
+    async fn register_and_submit(store: Arc<dyn Store>) {
+        let (status, body) = register(&store, "001").await;
+        assert_eq!(status, StatusCode::OK);
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let res = warp::test::request()
+            .path(&format!("/challenge/{}", token))
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let challenge: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(challenge["challenge"], body["challenge"]);
+
+        let wrong = vec![String::from("nope")];
+        assert_eq!(
+            submit(&store, &token, &wrong).await,
+            StatusCode::BAD_REQUEST
+        );
+        let soln = store
+            .retreive_soln(Uuid::parse_str(&token).unwrap())
+            .await
+            .unwrap()
+            .solution;
+        assert_eq!(submit(&store, &token, &soln).await, StatusCode::OK);
+        // Two attempts were allowed and both are used up now
+        assert_eq!(submit(&store, &token, &soln).await, StatusCode::FORBIDDEN);
+
+        let res = warp::test::request()
+            .path(&format!("/status/{}", token))
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        let status: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(status["attempts"], 2);
+        assert_eq!(status["passed"], true);
+        assert_eq!(status["remaining_attempts"], 0);
+    }
+
+    // Submissions racing each other still can't get past max_attempts between them
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_submissions() {
+        parallel_submissions(setup(cohort(-1, None)).await).await;
+
+        // A file, since an in memory database only ever hands out the one connection
+        let path = std::env::temp_dir().join(format!("{}.db", Uuid::new_v4()));
+        let store = SqliteStore::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        store.migrate().await.unwrap();
+        store.sync_cohort(&cohort(-1, None)).await.unwrap();
+        parallel_submissions(Arc::new(store)).await;
+        std::fs::remove_file(path).unwrap();
+    }
+
+    async fn parallel_submissions(store: Arc<dyn Store>) {
+        let (_, body) = register(&store, "001").await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let mut submissions = tokio::task::JoinSet::new();
+        for _ in 0..10 {
+            let (store, token) = (store.clone(), token.clone());
+            submissions.spawn(async move { submit(&store, &token, &vec![]).await });
+        }
+        let mut graded = 0;
+        while let Some(status) = submissions.join_next().await {
+            match status.unwrap() {
+                StatusCode::BAD_REQUEST => graded += 1,
+                status => assert_eq!(status, StatusCode::FORBIDDEN),
+            }
+        }
+        assert_eq!(graded, 2);
+
+        let res = warp::test::request()
+            .path(&format!("/status/{}", token))
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        let status: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(status["attempts"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_registration_errors() {
+        let store = setup(cohort(-1, None)).await;
+        assert_eq!(register(&store, "001").await.0, StatusCode::OK);
+        assert_eq!(register(&store, "001").await.0, StatusCode::CONFLICT);
+
+        let store = setup(cohort(1, None)).await;
+        assert_eq!(register(&store, "001").await.0, StatusCode::FORBIDDEN);
+
+        let store = setup(cohort(-2, Some(-1))).await;
+        assert_eq!(register(&store, "001").await.0, StatusCode::GONE);
+
+        let res = warp::test::request()
+            .path(&format!("/status/{}", Uuid::new_v4()))
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reviewer_routes_are_open_by_default() {
+        let store = setup(cohort(-1, None)).await;
+        let (_, body) = register(&store, "001").await;
+        submit(&store, body["token"].as_str().unwrap(), &vec![]).await;
+
+        let res = warp::test::request()
+            .path("/applicant/001")
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let applicant: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(applicant["ok"], false);
+        assert_eq!(applicant["cohort"], "test");
+
+        let res = warp::test::request()
+            .path("/applicants")
+            .json(&vec!["001", "002"])
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(
+            body["ApplicantsNotFound"]["applicants_not_found"],
+            json!(["002"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reviewer_routes_can_need_a_key() {
+        let store = setup(cohort(-1, None)).await;
+        let (_, body) = register(&store, "001").await;
+        submit(&store, body["token"].as_str().unwrap(), &vec![]).await;
+        let key = create_reviewer_key(store.as_ref(), "test", "alice", "pepper")
+            .await
+            .unwrap();
+        assert!(matches!(
+            create_reviewer_key(store.as_ref(), "test", "alice", "pepper").await,
+            Err(ModelError::DuplicateKey)
+        ));
+        let settings = ApplicationSettings {
+            require_reviewer_key: true,
+            api_key_pepper: Secret::new(String::from("pepper")),
+            ..settings()
+        };
+
+        for (auth, status) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("wrong"), StatusCode::UNAUTHORIZED),
+            (Some(ADMIN_KEY), StatusCode::OK),
+            (Some(key.as_str()), StatusCode::OK),
+        ] {
+            let mut req = warp::test::request().path("/applicant/001");
+            if let Some(auth) = auth {
+                req = req.header("authorization", format!("Bearer {}", auth));
+            }
+            let res = req
+                .reply(&end(Some(store.clone()), prepared(), settings.clone()))
+                .await;
+            assert_eq!(res.status(), status, "{:?}", auth);
+        }
+
+        revoke_reviewer_key(store.as_ref(), "test", "alice")
+            .await
+            .unwrap();
+        let res = warp::test::request()
+            .path("/applicant/001")
+            .header("authorization", format!("Bearer {}", key))
+            .reply(&end(Some(store.clone()), prepared(), settings))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_health_ready() {
+        let store = SqliteStore::connect("sqlite::memory:").await.unwrap();
+        store.migrate().await.unwrap();
+        let res = warp::test::request()
+            .path("/health/ready")
+            .reply(&end(Some(Arc::new(store)), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["ready"], json!(true));
+        assert_eq!(body["database"]["backend"], json!("sqlite"));
+        assert!(body["database"]["migration_version"].is_i64());
+    }
+
+    // Nothing listens on port 1, so startup gives up straight away and carries on degraded
+    #[tokio::test]
+    async fn test_health_ready_while_degraded() {
+        let database: DatabaseSettings = config::Config::builder()
+            .add_source(config::File::from_str(
+                "host: \"127.0.0.1\"\nport: 1\nusername: \"app\"\n\
+                 database_name: \"applications\"\nacquire_timeout_secs: 1\n\
+                 retry:\n  deadline_secs: 0\n  on_deadline: \"degraded\"\n",
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let (store, prepared) = retry::start(&database, &[cohort(-1, None)]).await.unwrap();
+        assert!(!prepared.get());
+        let res = warp::test::request()
+            .path("/health/ready")
+            .reply(&end(Some(store), prepared, settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["ready"], json!(false));
+        assert_eq!(body["database"]["reachable"], json!(false));
+
+        // Reachable and migrated still isn't ready until the cohorts are synced too
+        let store = SqliteStore::connect("sqlite::memory:").await.unwrap();
+        store.migrate().await.unwrap();
+        let res = warp::test::request()
+            .path("/health/ready")
+            .reply(&end(Some(Arc::new(store)), Prepared::new(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["database"]["reachable"], json!(true));
+        assert_eq!(body["database"]["error"], json!("not prepared yet"));
+    }
+
+    #[tokio::test]
+    async fn test_grant_extension() {
+        let store = setup(cohort(-1, None)).await;
+        let (_, body) = register(&store, "001").await;
+        let token = body["token"].as_str().unwrap().to_string();
+        let request = json!({"nuid": "001", "extra_attempts": 3, "reason": "testing"});
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/admin/extensions")
+            .json(&request)
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/admin/extensions")
+            .header("authorization", format!("Bearer {}", ADMIN_KEY))
+            .json(&request)
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = warp::test::request()
+            .path(&format!("/status/{}", token))
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        let status: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(status["remaining_attempts"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_is_admin_only() {
+        let store = setup(cohort(-1, None)).await;
+        let res = warp::test::request()
+            .path("/metrics")
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = warp::test::request()
+            .path("/metrics")
+            .header("authorization", format!("Bearer {}", ADMIN_KEY))
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_log_filter_is_admin_only() {
+        let store = setup(cohort(-1, None)).await;
+        let request = json!({"directives": ["generate_tech_app=debug"], "minutes": 0});
+
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/admin/log_filter")
+            .json(&request)
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = warp::test::request()
+            .method("PUT")
+            .path("/admin/log_filter")
+            .header("authorization", format!("Bearer {}", ADMIN_KEY))
+            .json(&request)
+            .reply(&end(Some(store.clone()), prepared(), settings()))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert!(body["InvalidLogFilter"]["reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("minutes"));
+    }
+
+    // Straight through the service main.rs serves, since the request ids get added
+    // underneath warp where warp::test can't see them
+    async fn call(store: &Arc<dyn Store>, request: Request<Body>) -> (String, Value) {
+        let mut service = RequestIds::new(
+            warp::service(end(Some(store.clone()), prepared(), settings())),
+            LogFields::default(),
+        );
+        let res = service.call(request).await.unwrap();
+        let id = res.headers()[X_REQUEST_ID].to_str().unwrap().to_string();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (id, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn status_request(id: Option<HeaderValue>) -> Request<Body> {
+        let mut request = Request::get(format!("/status/{}", Uuid::new_v4()));
+        if let Some(id) = id {
+            request = request.header(X_REQUEST_ID, id);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_ids() {
+        let store = setup(cohort(-1, None)).await;
+
+        let given = HeaderValue::from_static("fly-abc_123.4:5");
+        let (id, body) = call(&store, status_request(Some(given))).await;
+        assert_eq!(id, "fly-abc_123.4:5");
+        assert_eq!(body["request_id"], json!(id));
+
+        let (id, body) = call(&store, status_request(None)).await;
+        assert!(Uuid::parse_str(&id).is_ok());
+        assert_eq!(body["request_id"], json!(id));
+
+        // Anything that shouldn't go in a log line gets swapped for one of ours
+        let long = "a".repeat(129);
+        for hostile in [
+            HeaderValue::from_static(""),
+            HeaderValue::from_static("has spaces"),
+            HeaderValue::from_static("<script>alert(1)</script>"),
+            HeaderValue::from_static("\"quoted\""),
+            HeaderValue::from_str(&long).unwrap(),
+            HeaderValue::from_bytes("ünïcode".as_bytes()).unwrap(),
+        ] {
+            let (id, body) = call(&store, status_request(Some(hostile.clone()))).await;
+            assert!(
+                Uuid::parse_str(&id).is_ok(),
+                "{:?} came back as {}",
+                hostile,
+                id
+            );
+            assert_eq!(body["request_id"], json!(id));
+        }
+    }
+
+    // Collects every span that gets exported
+    #[derive(Clone, Debug, Default)]
+    struct Spans(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Spans {
+        fn export(
+            &mut self,
+            batch: Vec<SpanData>,
+        ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_and_db_spans_share_a_trace() {
+        let spans = Spans::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(spans.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        // Postgres, so the db calls get their spans, but nothing to connect to. The query
+        // still gets its span, it just fails
+        let store: Arc<dyn Store> = Arc::new(PgStore::new(
+            PgPoolOptions::new()
+                .acquire_timeout(std::time::Duration::from_millis(100))
+                .connect_lazy_with(PgConnectOptions::new().host("127.0.0.1").port(1)),
+        ));
+        let trace = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let request = Request::get(format!("/status/{}", Uuid::new_v4()))
+            .header("traceparent", format!("00-{}-00f067aa0ba902b7-01", trace))
+            .body(Body::empty())
+            .unwrap();
+        call(&store, request).await;
+        provider.force_flush();
+
+        let spans = spans.0.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.to_string()).collect();
+        for name in ["handle_get_status", "get_applicants_db"] {
+            assert!(
+                names.iter().any(|n| n == name),
+                "no {} in {:?}",
+                name,
+                names
+            );
+        }
+        assert!(names.iter().any(|n| n.starts_with("GET /status")));
+        for span in spans.iter() {
+            assert_eq!(
+                span.span_context.trace_id(),
+                TraceId::from_hex(trace).unwrap(),
+                "{} is on another trace",
+                span.name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cors_and_security_headers() {
+        let store = setup(cohort(-1, None)).await;
+        let settings = ApplicationSettings {
+            cors: CorsSettings {
+                allowed_origins: vec![String::from("https://apply.example.com")],
+                ..CorsSettings::default()
+            },
+            ..settings()
+        };
+
+        let res = warp::test::request()
+            .method("OPTIONS")
+            .path("/register")
+            .header("origin", "https://apply.example.com")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type")
+            .reply(&end(Some(store.clone()), prepared(), settings.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()["access-control-allow-origin"],
+            "https://apply.example.com"
+        );
+
+        // Errors need the CORS headers too or the portal can't read them
+        let res = warp::test::request()
+            .path("/status/not-a-token")
+            .header("origin", "https://apply.example.com")
+            .reply(&end(Some(store.clone()), prepared(), settings.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(res.headers().contains_key("access-control-allow-origin"));
+        assert_eq!(res.headers()["x-content-type-options"], "nosniff");
+        assert!(res.headers().contains_key("strict-transport-security"));
+
+        // Other origins still get answered, just without anything that lets a browser
+        // hand the response over
+        let res = warp::test::request()
+            .path("/health")
+            .header("origin", "https://evil.example.com")
+            .reply(&end(Some(store.clone()), prepared(), settings.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key("access-control-allow-origin"));
+        assert_eq!(res.headers()["referrer-policy"], "no-referrer");
+
+        // Their preflights are what get turned away
+        let res = warp::test::request()
+            .method("OPTIONS")
+            .path("/register")
+            .header("origin", "https://evil.example.com")
+            .header("access-control-request-method", "POST")
+            .reply(&end(Some(store.clone()), prepared(), settings.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(!res.headers().contains_key("access-control-allow-origin"));
+        assert_eq!(res.headers()["referrer-policy"], "no-referrer");
+    }
 }
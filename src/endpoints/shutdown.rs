@@ -0,0 +1,174 @@
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use warp::{Filter, Reply};
+
+// Keeps count of requests so shutdown can say how many it waited on
+#[derive(Clone, Default)]
+pub struct InFlight {
+    active: Arc<AtomicUsize>,
+    finished: Arc<AtomicU64>,
+}
+
+// Held for as long as a request is being handled
+pub struct InFlightGuard(InFlight);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+        self.0.finished.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn finished(&self) -> u64 {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    fn start(&self) -> InFlightGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+
+    // Wraps the whole api, the guard is dropped once the reply is built
+    pub fn track<F, R>(&self, filter: F) -> impl Filter<Extract = (R,), Error = Infallible> + Clone
+    where
+        F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+        R: Reply,
+    {
+        let in_flight = self.clone();
+        warp::any()
+            .map(move || in_flight.start())
+            .and(filter)
+            .map(|_guard: InFlightGuard, reply: R| reply)
+    }
+}
+
+// Gives the server up to `within` to finish the requests it already has, once it's been
+// told to stop. Past that it's aborted - connections still open go down with the runtime
+// when main returns. True if everything finished in time
+pub async fn drain(server: &mut JoinHandle<()>, within: Duration) -> bool {
+    match tokio::time::timeout(within, &mut *server).await {
+        Ok(_) => true,
+        Err(_) => {
+            server.abort();
+            false
+        }
+    }
+}
+
+// Resolves on the first SIGINT (what fly sends) or SIGTERM (docker, k8s)
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Got SIGINT"),
+        _ = terminate => info!("Got SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::watch;
+    use tokio::task::JoinHandle;
+    use warp::Filter;
+
+    use super::{drain, InFlight};
+    use crate::config::LogFields;
+    use crate::endpoints::http;
+    use crate::endpoints::request_id::RequestIds;
+
+    // A server whose only route takes `delay` to answer, and the sender that stops it
+    fn start(delay: Duration) -> (SocketAddr, InFlight, watch::Sender<bool>, JoinHandle<()>) {
+        let in_flight = InFlight::new();
+        let routes = in_flight.track(warp::any().then(move || async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        }));
+        let (stop, mut stopped) = watch::channel(false);
+        let (addr, server) = http::serve(
+            RequestIds::new(warp::service(routes), LogFields::default()),
+            ([127, 0, 0, 1], 0).into(),
+            async move {
+                let _ = stopped.changed().await;
+            },
+        )
+        .unwrap();
+        (addr, in_flight, stop, tokio::spawn(server))
+    }
+
+    async fn get(addr: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await;
+        response
+    }
+
+    async fn in_flight(in_flight: &InFlight) {
+        while in_flight.active() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain_finishes_in_flight_requests() {
+        let (addr, tracked, stop, mut server) = start(Duration::from_millis(200));
+        let request = tokio::spawn(get(addr));
+        in_flight(&tracked).await;
+
+        stop.send(true).unwrap();
+        let started = Instant::now();
+        assert!(drain(&mut server, Duration::from_secs(5)).await);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(request.await.unwrap().ends_with("done"));
+        assert_eq!(tracked.finished(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_drain_gives_up_after_the_window() {
+        let (addr, tracked, stop, mut server) = start(Duration::from_secs(60));
+        tokio::spawn(get(addr));
+        in_flight(&tracked).await;
+
+        stop.send(true).unwrap();
+        let started = Instant::now();
+        assert!(!drain(&mut server, Duration::from_millis(200)).await);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(server.await.unwrap_err().is_cancelled());
+        assert_eq!(tracked.active(), 1);
+    }
+}
@@ -0,0 +1,331 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use hyper::server::accept;
+use hyper::service::{make_service_fn, Service};
+use hyper::{Body, Request, Response};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use warp::host::Authority;
+use warp::http::{StatusCode, Uri};
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+use super::request_id::RequestIds;
+use crate::config::TlsSettings;
+
+// How often the cert and key get checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+// Anyone who hasn't finished a handshake by now isn't going to
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Accept errors are mostly running out of file descriptors, which retrying straight
+// away won't fix. Back off a little so the loop doesn't spin on them
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Hands every handshake whatever cert is loaded right now, so swapping it out doesn't
+// touch connections that are already up
+pub struct CertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn load(settings: &TlsSettings) -> io::Result<Self> {
+        Ok(Self {
+            current: RwLock::new(Arc::new(load_certified_key(settings)?)),
+        })
+    }
+
+    // A bad cert or key leaves the old one in place
+    pub fn reload(&self, settings: &TlsSettings) -> io::Result<()> {
+        let key = Arc::new(load_certified_key(settings)?);
+        *self.current.write().unwrap() = key;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn load_certified_key(settings: &TlsSettings) -> io::Result<CertifiedKey> {
+    let certs: Vec<Certificate> =
+        rustls_pemfile::certs(&mut BufReader::new(File::open(&settings.cert)?))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+    if certs.is_empty() {
+        return Err(invalid(format!(
+            "no certificates in {}",
+            settings.cert.display()
+        )));
+    }
+
+    let mut reader = BufReader::new(File::open(&settings.key)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => {
+                return Err(invalid(format!(
+                    "no private key in {}",
+                    settings.key.display()
+                )))
+            }
+        }
+    };
+    let key = sign::any_supported_type(&key)
+        .map_err(|e| invalid(format!("{}: {}", settings.key.display(), e)))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Reloads on SIGHUP, and whenever either file's mtime moves (certbot, cert-manager etc.)
+pub fn watch(resolver: Arc<CertResolver>, settings: TlsSettings) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("Failed to listen for SIGHUP");
+        let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+        let mut last = (modified(&settings.cert), modified(&settings.key));
+
+        loop {
+            #[cfg(unix)]
+            let hangup = hangup.recv();
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = hangup => info!("Got SIGHUP, reloading TLS cert"),
+                _ = ticker.tick() => {
+                    let now = (modified(&settings.cert), modified(&settings.key));
+                    if now == last {
+                        continue;
+                    }
+                    info!("TLS cert or key changed, reloading");
+                }
+            }
+            last = (modified(&settings.cert), modified(&settings.key));
+            match resolver.reload(&settings) {
+                Ok(()) => info!("Reloaded TLS cert from {}", settings.cert.display()),
+                Err(e) => error!("Couldn't reload TLS cert, keeping the old one: {}", e),
+            }
+        }
+    });
+}
+
+// Same deal as warp's try_bind_with_graceful_shutdown, just over rustls. Handshakes
+// happen off the accept loop so a slow client can't hold everyone else up
+pub fn serve<S>(
+    service: RequestIds<S>,
+    addr: SocketAddr,
+    resolver: Arc<CertResolver>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<(SocketAddr, impl Future<Output = ()>)>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let addr = listener.local_addr()?;
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept a connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                },
+                // The server's gone, so nobody's listening anymore
+                _ = tx.closed() => break,
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok::<_, io::Error>(stream)).await;
+                    }
+                    Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+                    Err(_) => debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+    });
+
+    let server = hyper::Server::builder(accept::from_stream(ReceiverStream::new(rx)))
+        .serve(make_service_fn(move |stream: &TlsStream<TcpStream>| {
+            let service = service.connection(stream.get_ref().0.peer_addr().ok());
+            async move { Ok::<_, Infallible>(service) }
+        }))
+        .with_graceful_shutdown(shutdown);
+
+    Ok((addr, async move {
+        if let Err(e) = server.await {
+            error!("TLS server error: {}", e);
+        }
+    }))
+}
+
+// For the plain http listener - everything gets a permanent redirect to the same
+// path on https. Falls back to application.host when there's no Host header
+pub fn redirect(
+    https_port: u16,
+    fallback_host: String,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    warp::host::optional()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(
+            move |authority: Option<Authority>, path: FullPath, query: String| {
+                let host = authority
+                    .map(|authority| authority.host().to_string())
+                    .unwrap_or_else(|| fallback_host.clone());
+                let port = match https_port {
+                    443 => String::new(),
+                    port => format!(":{}", port),
+                };
+                let query = match query.as_str() {
+                    "" => String::new(),
+                    query => format!("?{}", query),
+                };
+                match Uri::try_from(format!(
+                    "https://{}{}{}{}",
+                    host,
+                    port,
+                    path.as_str(),
+                    query
+                )) {
+                    Ok(uri) => warp::redirect::permanent(uri).into_response(),
+                    Err(_) => StatusCode::BAD_REQUEST.into_response(),
+                }
+            },
+        )
+        .recover(|_: Rejection| async { Ok::<_, Infallible>(StatusCode::BAD_REQUEST) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+    use warp::http::StatusCode;
+
+    use super::{redirect, CertResolver};
+    use crate::config::TlsSettings;
+
+    // A throwaway self-signed cert for localhost, only ever used here
+    const CERT: &str = include_str!("testdata/localhost.crt");
+    const KEY: &str = include_str!("testdata/localhost.key");
+
+    #[tokio::test]
+    async fn test_redirect() {
+        let filter = redirect(8443, String::from("apply.example.com"));
+
+        let res = warp::test::request()
+            .path("/status/abc?verbose=1")
+            .header("host", "generate.example.com:8080")
+            .reply(&filter)
+            .await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            res.headers()["location"],
+            "https://generate.example.com:8443/status/abc?verbose=1"
+        );
+
+        let res = warp::test::request().path("/health").reply(&filter).await;
+        assert_eq!(
+            res.headers()["location"],
+            "https://apply.example.com:8443/health"
+        );
+
+        let res = warp::test::request()
+            .path("/")
+            .header("host", "generate.example.com")
+            .reply(&redirect(443, String::new()))
+            .await;
+        assert_eq!(res.headers()["location"], "https://generate.example.com/");
+    }
+
+    #[test]
+    fn test_reload_keeps_the_old_cert() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir).unwrap();
+        let settings = TlsSettings {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            redirect_port: None,
+        };
+        let write = |cert: &str, key: &str| {
+            std::fs::write(&settings.cert, cert).unwrap();
+            std::fs::write(&settings.key, key).unwrap();
+        };
+        write(CERT, KEY);
+        let resolver = CertResolver::load(&settings).unwrap();
+        let current = |resolver: &CertResolver| resolver.current.read().unwrap().clone();
+        let loaded = current(&resolver);
+
+        for (cert, key) in [
+            ("", KEY),
+            (CERT, ""),
+            (CERT, CERT),
+            ("not a cert", "not a key"),
+        ] {
+            write(cert, key);
+            assert!(resolver.reload(&settings).is_err());
+            assert!(Arc::ptr_eq(&loaded, &current(&resolver)));
+        }
+        let missing = TlsSettings {
+            cert: PathBuf::from("/does/not/exist"),
+            ..settings.clone()
+        };
+        assert!(resolver.reload(&missing).is_err());
+        assert!(Arc::ptr_eq(&loaded, &current(&resolver)));
+
+        write(CERT, KEY);
+        resolver.reload(&settings).unwrap();
+        assert!(!Arc::ptr_eq(&loaded, &current(&resolver)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,9 @@
+// Everything lives in here so the admin binary can share it with the server
+#[macro_use]
+extern crate tracing;
+
+pub mod config;
+pub mod db;
+pub mod endpoints;
+pub mod model;
+pub mod telemetry;
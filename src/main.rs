@@ -1,40 +1,215 @@
-extern crate pretty_env_logger;
 #[macro_use]
-extern crate log;
-use sqlx::PgPool;
+extern crate tracing;
 
+use clap::{Parser, Subcommand};
 use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
 
-use crate::config::get_configuration;
+use generate_tech_app::config::{load_configuration, ConfigOptions, Settings};
+use generate_tech_app::endpoints::request_id::RequestIds;
+use generate_tech_app::endpoints::shutdown::{self, InFlight};
+use generate_tech_app::endpoints::tls::{self, CertResolver};
+use generate_tech_app::endpoints::{http, metrics};
+use generate_tech_app::{db, endpoints, telemetry};
 
-mod config;
-mod db;
-mod endpoints;
-mod model;
+#[derive(Parser)]
+#[command(about = "The Generate tech application server")]
+struct Cli {
+    /// Port to listen on, over application.port
+    #[arg(long, global = true)]
+    port: Option<u16>,
+    /// Address to listen on, over application.bind
+    #[arg(long, global = true)]
+    bind: Option<IpAddr>,
+    /// Where base.yaml and the environment files live [default: ./configuration]
+    #[arg(long, global = true)]
+    config_dir: Option<PathBuf>,
+    /// Environment to load, over APP_ENVIRONMENT [default: local]
+    #[arg(long, global = true)]
+    env: Option<String>,
+    /// Same syntax as RUST_LOG, which it replaces
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Cli {
+    // The flags win over everything get_configuration would have picked up
+    fn config_options(&self) -> ConfigOptions {
+        let mut overrides = Vec::new();
+        if let Some(port) = self.port {
+            overrides.push((String::from("application.port"), port.to_string()));
+        }
+        if let Some(bind) = self.bind {
+            overrides.push((String::from("application.bind"), bind.to_string()));
+        }
+        ConfigOptions {
+            config_dir: self.config_dir.clone(),
+            environment: self.env.clone(),
+            overrides,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Load and validate the configuration, print it (secrets redacted) and exit
+    CheckConfig,
+}
 
-// Gonna need to handle TLS certs here when I deploy - lets look at NGINX
+// TLS is optional - on fly the proxy in front does it for us
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
     let _ = dotenv::dotenv();
-    pretty_env_logger::init();
+    let cli = Cli::parse();
 
-    let configuration = get_configuration().expect("Failed to read configuration file");
+    // Nothing's set up to log yet, the logging settings come from in here
+    let configuration = match load_configuration(&cli.config_options()) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("error: failed to load configuration: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = telemetry::init(
+        &configuration.logging,
+        Some(&configuration.tracing),
+        cli.log_level.as_deref(),
+    ) {
+        eprintln!("error: failed to set up logging: {}", e);
+        return ExitCode::FAILURE;
+    }
 
-    info!("{:?}", configuration);
-    let conn_string = configuration.connection_string();
+    if let Some(Command::CheckConfig) = cli.command {
+        return check_config(&configuration);
+    }
+
+    let problems = configuration.validate();
+    for problem in &problems {
+        if problem.is_error() {
+            error!("{}", problem);
+        } else {
+            warn!("{}", problem);
+        }
+    }
+    if problems.iter().any(|problem| problem.is_error()) {
+        error!("Not starting with an invalid configuration, run check-config for details");
+        return ExitCode::FAILURE;
+    }
+
+    match serve(configuration).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    info!("{:?}", conn_string);
-    let pool = PgPool::connect(&conn_string).await?;
+fn check_config(configuration: &Settings) -> ExitCode {
+    println!("{:#?}", configuration);
+    let problems = configuration.validate();
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.iter().any(|problem| problem.is_error()) {
+        ExitCode::FAILURE
+    } else {
+        println!("Configuration is valid");
+        ExitCode::SUCCESS
+    }
+}
 
-    info!("Connection established to Postgres DB");
+async fn serve(configuration: Settings) -> Result<(), Box<dyn Error>> {
+    info!("{:?}", configuration);
+    info!("Connecting to {}", configuration.database.describe());
+    let (store, prepared) =
+        db::retry::start(&configuration.database, &configuration.cohorts).await?;
 
-    sqlx::migrate!().run(&pool).await?;
+    info!("Connection established to DB");
 
     info!("Starting submission server");
 
-    warp::serve(endpoints::end(Some(pool)))
-        .run(([0, 0, 0, 0], configuration.port()))
-        .await;
+    // Flipped once to stop every listener
+    let (stop, stopped) = watch::channel(false);
+    let stopped = move || {
+        let mut stopped = stopped.clone();
+        async move {
+            let _ = stopped.changed().await;
+        }
+    };
+
+    let application = &configuration.application;
+    let in_flight = InFlight::new();
+    let routes = in_flight.track(metrics::instrument(endpoints::end(
+        Some(store.clone()),
+        prepared,
+        application.clone(),
+    )));
+    let service = RequestIds::new(warp::service(routes), configuration.logging.fields.clone());
+    let addr = SocketAddr::new(application.bind, application.port);
+    let mut server = match &application.tls {
+        Some(settings) => {
+            let resolver = Arc::new(CertResolver::load(settings)?);
+            tls::watch(resolver.clone(), settings.clone());
+            let (addr, server) = tls::serve(service, addr, resolver, stopped())?;
+            info!("Listening on https://{}", addr);
+
+            if let Some(port) = settings.redirect_port {
+                let (addr, redirect) =
+                    warp::serve(tls::redirect(application.port, application.host.clone()))
+                        .try_bind_with_graceful_shutdown(
+                            SocketAddr::new(application.bind, port),
+                            stopped(),
+                        )?;
+                info!("Redirecting http://{} to https", addr);
+                tokio::spawn(redirect);
+            }
+            tokio::spawn(server)
+        }
+        None => {
+            let (addr, server) = http::serve(service, addr, stopped())?;
+            info!("Listening on http://{}", addr);
+            tokio::spawn(server)
+        }
+    };
+
+    shutdown::signal().await;
+    // One deadline for all of shutdown, so closing the db only gets what draining left
+    let deadline = Instant::now() + Duration::from_secs(application.kill_timeout_secs);
+    let drain = Duration::from_secs(application.drain_secs);
+    let finished = in_flight.finished();
+    info!(
+        "Shutting down, waiting up to {:?} on {} requests",
+        drain,
+        in_flight.active()
+    );
+    let _ = stop.send(true);
+
+    // Past the drain period whatever's left gets cut off, same as before
+    if shutdown::drain(&mut server, drain).await {
+        info!("Drained {} requests", in_flight.finished() - finished);
+    } else {
+        warn!(
+            "Drained {} requests, gave up on {} still running",
+            in_flight.finished() - finished,
+            in_flight.active()
+        );
+    }
+
+    // A request cut off above can still have a connection checked out
+    match tokio::time::timeout_at(deadline, store.close()).await {
+        Ok(()) => info!("Closed the DB connections, bye"),
+        Err(_) => warn!("Gave up waiting on the DB connections to close, bye"),
+    }
+    telemetry::shutdown().await;
 
     Ok(())
 }
@@ -0,0 +1,271 @@
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, Rng};
+use serde_json::json;
+use std::fmt::Display;
+use uuid::Uuid;
+
+use crate::{
+    config::ChallengeSettings,
+    db::{transactions::AuditEntry, Store, StoreError},
+    endpoints::errors::ModelError,
+};
+
+use super::engine::{extra_time, generate_challenge, mandatory_cases, time_to_completion};
+use super::types::ApplicantSummary;
+
+// These back the admin cli. Anything that takes a cohort falls back to the
+// applicant's most recent one when it isn't given, and anything that changes
+// something is audited as `actor`
+
+pub async fn list_applicants(
+    store: &dyn Store,
+    cohort: Option<&str>,
+    search: Option<&str>,
+) -> Result<Vec<ApplicantSummary>, ModelError> {
+    match store.list_applicants(cohort, search).await {
+        Ok(records) => Ok(records
+            .into_iter()
+            .map(|record| ApplicantSummary {
+                time_to_completion: record.first_success.map(|sub_time| {
+                    time_to_completion(&record.registration_time, &sub_time, record.extra_seconds)
+                }),
+                passed: record.first_success.is_some(),
+                nuid: record.nuid,
+                name: record.applicant_name,
+                cohort: record.cohort_name,
+                registration_time: record.registration_time,
+                attempts: record.attempts,
+                late: record.late,
+                extra_time: extra_time(record.extra_seconds),
+                extra_attempts: record.extra_attempts,
+            })
+            .collect()),
+        Err(_) => Err(ModelError::SqlError),
+    }
+}
+
+async fn find_applicant(
+    store: &dyn Store,
+    nuid: &str,
+    cohort: Option<&str>,
+) -> Result<(i32, String, ChallengeSettings), ModelError> {
+    match store.find_applicant(nuid, cohort).await {
+        Ok(found) => Ok(found),
+        Err(StoreError::NotFound) => Err(ModelError::NoUserFound),
+        Err(_) => Err(ModelError::SqlError),
+    }
+}
+
+// Every change writes its audit row in the same transaction as the change itself
+pub(super) fn entry(actor: &str, action: &str, detail: serde_json::Value) -> AuditEntry {
+    AuditEntry {
+        actor: actor.to_string(),
+        action: action.to_string(),
+        detail,
+        performed_at: Utc::now(),
+    }
+}
+
+// Same challenge, all attempts back. Returns the cohort and how many submissions went
+pub async fn reset_challenge(
+    store: &dyn Store,
+    actor: &str,
+    nuid: &str,
+    cohort: Option<&str>,
+) -> Result<(String, u64), ModelError> {
+    let result = async {
+        let (cohort_id, cohort_name, _) = find_applicant(store, nuid, cohort).await?;
+        let audit = entry(
+            actor,
+            "reset",
+            json!({ "nuid": nuid, "cohort": cohort_name }),
+        );
+        match store.reset_submissions(cohort_id, nuid, &audit).await {
+            Ok(deleted) => Ok((cohort_name, deleted)),
+            Err(_) => Err(ModelError::SqlError),
+        }
+    }
+    .await;
+    let detail = json!({ "nuid": nuid, "cohort": cohort });
+    audit_failure(store, actor, "reset", detail, result).await
+}
+
+// Brand new strings off a random seed, for when a challenge has leaked. Their old
+// submissions get cleared since they were graded against the old one
+pub async fn regenerate_challenge(
+    store: &dyn Store,
+    actor: &str,
+    nuid: &str,
+    cohort: Option<&str>,
+) -> Result<(String, Vec<String>), ModelError> {
+    let result = async {
+        let (cohort_id, cohort_name, challenge) = find_applicant(store, nuid, cohort).await?;
+        let seed: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let (challenge_strings, solution) = generate_challenge(
+            &format!("{}{}", seed, nuid),
+            challenge.size,
+            mandatory_cases(),
+        );
+
+        let audit = entry(
+            actor,
+            "regenerate",
+            json!({ "nuid": nuid, "cohort": cohort_name }),
+        );
+        match store
+            .replace_challenge(cohort_id, nuid, &challenge_strings, &solution, &audit)
+            .await
+        {
+            Ok(()) => Ok((cohort_name, challenge_strings)),
+            Err(_) => Err(ModelError::SqlError),
+        }
+    }
+    .await;
+    let detail = json!({ "nuid": nuid, "cohort": cohort });
+    audit_failure(store, actor, "regenerate", detail, result).await
+}
+
+// Swaps in a fresh token so the old one stops working
+pub async fn revoke_token(
+    store: &dyn Store,
+    actor: &str,
+    nuid: &str,
+    cohort: Option<&str>,
+) -> Result<(String, Uuid), ModelError> {
+    let result = async {
+        let (cohort_id, cohort_name, _) = find_applicant(store, nuid, cohort).await?;
+        let token = Uuid::new_v4();
+        let audit = entry(
+            actor,
+            "revoke-token",
+            json!({ "nuid": nuid, "cohort": cohort_name }),
+        );
+        match store.replace_token(cohort_id, nuid, token, &audit).await {
+            Ok(()) => Ok((cohort_name, token)),
+            Err(_) => Err(ModelError::SqlError),
+        }
+    }
+    .await;
+    let detail = json!({ "nuid": nuid, "cohort": cohort });
+    audit_failure(store, actor, "revoke-token", detail, result).await
+}
+
+// Takes their submissions and extensions with them
+pub async fn delete_applicant(
+    store: &dyn Store,
+    actor: &str,
+    nuid: &str,
+    cohort: Option<&str>,
+) -> Result<String, ModelError> {
+    let result = async {
+        let (cohort_id, cohort_name, _) = find_applicant(store, nuid, cohort).await?;
+        let audit = entry(
+            actor,
+            "delete",
+            json!({ "nuid": nuid, "cohort": cohort_name }),
+        );
+        match store.delete_applicant(cohort_id, nuid, &audit).await {
+            Ok(()) => Ok(cohort_name),
+            Err(_) => Err(ModelError::SqlError),
+        }
+    }
+    .await;
+    let detail = json!({ "nuid": nuid, "cohort": cohort });
+    audit_failure(store, actor, "delete", detail, result).await
+}
+
+// For the actions that only read, there's nothing to share a transaction with
+pub async fn audit(
+    store: &dyn Store,
+    actor: &str,
+    action: &str,
+    detail: serde_json::Value,
+) -> Result<(), ModelError> {
+    match store.write_audit_log(&entry(actor, action, detail)).await {
+        Ok(()) => Ok(()),
+        Err(_) => Err(ModelError::SqlError),
+    }
+}
+
+// Attempts that didn't go through get a row too, with the error in the detail. The db
+// being down is as likely a reason as any, so not managing to write it just gets logged
+pub async fn audit_failure<T, E: Display>(
+    store: &dyn Store,
+    actor: &str,
+    action: &str,
+    mut detail: serde_json::Value,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    if let Err(e) = &result {
+        if let Some(detail) = detail.as_object_mut() {
+            detail.insert("error".into(), json!(e.to_string()));
+        }
+        if let Err(write_err) = store.write_audit_log(&entry(actor, action, detail)).await {
+            error!("Couldn't audit a failed {}: {}", action, write_err);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::{delete_applicant, reset_challenge};
+    use crate::config::{ChallengeSettings, CohortSettings};
+    use crate::db::{MemoryStore, Store};
+    use crate::model::engine::register_user;
+
+    async fn setup() -> MemoryStore {
+        let store = MemoryStore::new();
+        store
+            .sync_cohort(&CohortSettings {
+                name: String::from("test"),
+                opens_at: Utc::now() - Duration::days(1),
+                closes_at: None,
+                submission_deadline: None,
+                challenge: ChallengeSettings::default(),
+            })
+            .await
+            .unwrap();
+        register_user(&store, String::from("Test Applicant"), String::from("001"))
+            .await
+            .unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_audited() {
+        let store = setup().await;
+        reset_challenge(&store, "alice", "001", None).await.unwrap();
+        delete_applicant(&store, "bob", "001", None).await.unwrap();
+
+        let log = store.audit_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(
+            (log[0].actor.as_str(), log[0].action.as_str()),
+            ("alice", "reset")
+        );
+        assert_eq!(log[0].detail["cohort"], "test");
+        assert_eq!(
+            (log[1].actor.as_str(), log[1].action.as_str()),
+            ("bob", "delete")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_audited() {
+        let store = setup().await;
+        assert!(reset_challenge(&store, "alice", "002", None).await.is_err());
+
+        let log = store.audit_log();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].action, "reset");
+        assert_eq!(log[0].detail["nuid"], "002");
+        assert!(log[0].detail["error"].is_string());
+    }
+}
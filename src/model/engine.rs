@@ -1,108 +1,348 @@
+use chrono::{DateTime, Utc};
 use rand::{
     seq::{IteratorRandom, SliceRandom},
     Rng,
 };
-use sqlx::PgPool;
+use std::time::Duration;
 
 use uuid::Uuid;
 
 use crate::{
-    db::{self},
+    config::{ChallengeSettings, CohortSettings},
+    db::{
+        transactions::{ApplicantLookup, GradeOutcome, GradingContext, RegistrationWindow},
+        Store, StoreError,
+    },
     endpoints::errors::ModelError,
 };
 
-use super::types::{Applicant, Color};
+use super::types::{
+    Applicant, ApplicantStatus, BuildInfo, Color, DatabaseHealth, Extension, Readiness,
+};
 
 use strum::{EnumIter, IntoEnumIterator};
 
 use rand_pcg::Pcg64;
 use rand_seeder::Seeder;
 
+pub async fn sync_cohorts(store: &dyn Store, cohorts: &[CohortSettings]) -> Result<(), ModelError> {
+    for cohort in cohorts {
+        if store.sync_cohort(cohort).await.is_err() {
+            return Err(ModelError::SqlError);
+        }
+    }
+    Ok(())
+}
+
+// Only applicants who've submitted something, reviewers have nothing to look at otherwise
 pub async fn get_applicants(
-    pool: PgPool,
+    store: &dyn Store,
     applicants: &[String],
+    cohort: Option<&str>,
 ) -> Result<Vec<Applicant>, ModelError> {
-    match db::transactions::get_applicants_db(&pool, applicants).await {
+    match store
+        .get_applicants(ApplicantLookup::Nuids(applicants), cohort)
+        .await
+    {
         Ok(vec) => Ok(vec
-            .iter()
-            .map(|(nuid, name, reg_time, sub_time, ok)| {
-                let time_to_completion = match sub_time.signed_duration_since(*reg_time).to_std() {
-                    Ok(d) => d,
-                    Err(_) => std::time::Duration::ZERO,
-                };
-                Applicant {
-                    nuid: nuid.clone(),
-                    name: name.clone(),
-                    time_to_completion,
-                    ok: *ok,
-                }
+            .into_iter()
+            .filter_map(|record| {
+                Some(Applicant {
+                    time_to_completion: time_to_completion(
+                        &record.registration_time,
+                        &record.submission_time?,
+                        record.extra_seconds,
+                    ),
+                    ok: record.ok?,
+                    late: record.late?,
+                    extra_time: extra_time(record.extra_seconds),
+                    extra_attempts: record.extra_attempts,
+                    nuid: record.nuid,
+                    name: record.applicant_name,
+                    cohort: record.cohort_name,
+                })
             })
             .collect()),
         Err(_) => Err(ModelError::SqlError),
     }
 }
+
+pub(super) fn extra_time(extra_seconds: i64) -> Duration {
+    Duration::from_secs(extra_seconds.max(0) as u64)
+}
+
+// Extensions push the deadline back and raise the attempt limit, but only when there is one
+fn extend(
+    deadline: Option<DateTime<Utc>>,
+    max_attempts: Option<i64>,
+    (extra_seconds, extra_attempts): (i64, i64),
+) -> (Option<DateTime<Utc>>, Option<i64>) {
+    (
+        deadline.map(|deadline| deadline + chrono::Duration::seconds(extra_seconds)),
+        max_attempts.map(|max| max + extra_attempts),
+    )
+}
+
+// Extra time doesn't count against the applicant, so it comes off the clock too
+pub(super) fn time_to_completion(
+    reg_time: &DateTime<Utc>,
+    sub_time: &DateTime<Utc>,
+    extra_seconds: i64,
+) -> Duration {
+    match sub_time.signed_duration_since(*reg_time).to_std() {
+        Ok(d) => d.saturating_sub(extra_time(extra_seconds)),
+        Err(_) => Duration::ZERO,
+    }
+}
+
+// Same query the reviewers go through with get_applicants, just found by token so it
+// only ever covers the caller
+pub async fn get_status(store: &dyn Store, token: Uuid) -> Result<ApplicantStatus, ModelError> {
+    let record = match store
+        .get_applicants(ApplicantLookup::Token(token), None)
+        .await
+    {
+        Ok(mut records) => match records.pop() {
+            Some(record) => record,
+            None => return Err(ModelError::NoUserFound),
+        },
+        Err(_) => return Err(ModelError::SqlError),
+    };
+    let (deadline, max_attempts) = extend(
+        record.submission_deadline,
+        record.max_attempts,
+        (record.extra_seconds, record.extra_attempts),
+    );
+    Ok(ApplicantStatus {
+        registration_time: record.registration_time,
+        attempts: record.attempts,
+        passed: record.first_success.is_some(),
+        first_success: record.first_success,
+        time_to_completion: record.first_success.as_ref().map(|sub_time| {
+            time_to_completion(&record.registration_time, sub_time, record.extra_seconds)
+        }),
+        remaining_attempts: max_attempts.map(|max| (max - record.attempts).max(0)),
+        deadline,
+    })
+}
+
 pub async fn register_user(
-    pool: PgPool,
+    store: &dyn Store,
     name: String,
     nuid: String,
 ) -> Result<(Uuid, Vec<String>), ModelError> {
+    let now = Utc::now();
     let token = Uuid::new_v4();
-    let (challenge_strings, solution) = generate_challenge(
-        &nuid,
-        100,
-        vec![
-            String::from(""),
-            Color::Red.to_string(),
-            Color::Orange.to_string(),
-            Color::Yellow.to_string(),
-            Color::Green.to_string(),
-            Color::Blue.to_string(),
-            Color::Violet.to_string(),
-        ],
-    );
+    // Runs inside the store's transaction, once it knows which cohort this is for
+    let build = |cohort_id: i32, challenge: &ChallengeSettings| {
+        let seed = challenge
+            .seed
+            .as_ref()
+            .map(|seed| seed.expose().clone())
+            .unwrap_or_else(|| cohort_id.to_string());
+        generate_challenge(
+            &format!("{}{}", seed, nuid),
+            challenge.size,
+            mandatory_cases(),
+        )
+    };
 
-    match db::transactions::register_user_db(&pool, token, name, nuid, &challenge_strings, solution)
+    match store
+        .register_user(now, token, name, nuid.clone(), &build)
         .await
     {
-        Ok(()) => Ok((token, challenge_strings)),
-        // there's a bunch of different ways that this can fail, I should probably
-        // handle the error -
-        Err(_e) => Err(ModelError::DuplicateUser),
+        Ok(Some(challenge_strings)) => Ok((token, challenge_strings)),
+        Ok(None) => Err(registration_window_error(store, now).await),
+        Err(StoreError::Duplicate) => Err(ModelError::DuplicateUser),
+        Err(_) => Err(ModelError::SqlError),
+    }
+}
+
+// Tells people showing up early apart from people showing up late
+async fn registration_window_error(store: &dyn Store, now: DateTime<Utc>) -> ModelError {
+    match store.registration_window(now).await {
+        Ok(RegistrationWindow {
+            next_opens_at: Some(opens_at),
+            ..
+        }) => ModelError::RegistrationNotOpen { opens_at },
+        Ok(window) => ModelError::RegistrationClosed {
+            closed_at: window.last_closes_at,
+        },
+        Err(_) => ModelError::SqlError,
     }
 }
 
-pub async fn retreive_token(pool: PgPool, nuid: &String) -> Result<Uuid, ModelError> {
-    match db::transactions::retreive_token_db(&pool, nuid).await {
+pub async fn retreive_token(store: &dyn Store, nuid: &str) -> Result<Uuid, ModelError> {
+    match store.retreive_token(nuid).await {
         Ok(token) => Ok(token),
         Err(_) => Err(ModelError::NoUserFound),
     }
 }
 
-pub async fn retreive_challenge(pool: &PgPool, token: Uuid) -> Result<Vec<String>, ModelError> {
-    match db::transactions::retreive_challenge_db(pool, token).await {
+pub async fn retreive_challenge(store: &dyn Store, token: Uuid) -> Result<Vec<String>, ModelError> {
+    match store.retreive_challenge(token).await {
         Ok(challenge) => Ok(challenge),
         Err(_) => Err(ModelError::NoUserFound),
     }
 }
 
+// Returns whether the solution was correct, and whether it came in after the deadline.
+// The store grades and records it in one transaction, so two submissions landing at
+// once can't both slip in under the attempt limit
 pub async fn check_solution(
-    pool: PgPool,
+    store: &dyn Store,
     token: Uuid,
     given_soln: &Vec<String>,
-) -> Result<bool, ModelError> {
-    // Check if the solution is correct - write the row to the solutions table
-    match db::transactions::retreive_soln(&pool, token).await {
-        Ok((soln, nuid)) => {
-            let ok = soln == *given_soln;
-            if let Err(_e) = db::transactions::write_submission(pool, nuid, ok).await {
-                return Err(ModelError::SqlError);
+) -> Result<(bool, bool), ModelError> {
+    let submission_time = Utc::now();
+    let grade = |context: &GradingContext| {
+        let (deadline, max_attempts) = extend(
+            context.deadline,
+            context.challenge.max_attempts,
+            context.extension,
+        );
+        if let Some(max_attempts) = max_attempts {
+            if context.attempts >= max_attempts {
+                return GradeOutcome::OutOfAttempts { max_attempts };
             }
-            Ok(ok)
         }
-        Err(_) => Err(ModelError::NoUserFound),
+        GradeOutcome::Graded {
+            ok: context.solution == *given_soln,
+            // Late submissions still count, reviewers just get to see that they were late
+            late: deadline.is_some_and(|deadline| submission_time > deadline),
+        }
+    };
+
+    match store.grade_submission(token, submission_time, &grade).await {
+        Ok(GradeOutcome::Graded { ok, late }) => Ok((ok, late)),
+        Ok(GradeOutcome::OutOfAttempts { max_attempts }) => {
+            Err(ModelError::NoAttemptsRemaining { max_attempts })
+        }
+        Err(StoreError::NotFound) => Err(ModelError::NoUserFound),
+        Err(_) => Err(ModelError::SqlError),
+    }
+}
+
+pub async fn grant_extension(
+    store: &dyn Store,
+    nuid: &str,
+    cohort: Option<&str>,
+    extra_time: Duration,
+    extra_attempts: i64,
+    reason: &str,
+) -> Result<Extension, ModelError> {
+    if extra_attempts < 0 || reason.trim().is_empty() {
+        return Err(ModelError::InvalidExtension);
+    }
+    let extra_seconds = match i64::try_from(extra_time.as_secs()) {
+        Ok(secs) => secs,
+        Err(_) => return Err(ModelError::InvalidExtension),
+    };
+
+    match store
+        .grant_extension(
+            nuid,
+            cohort,
+            extra_seconds,
+            extra_attempts,
+            reason,
+            Utc::now(),
+        )
+        .await
+    {
+        Ok((cohort, extra_seconds, extra_attempts, deadline)) => Ok(Extension {
+            nuid: nuid.to_string(),
+            cohort,
+            extra_time: self::extra_time(extra_seconds),
+            extra_attempts,
+            deadline,
+        }),
+        Err(StoreError::NotFound) => Err(ModelError::NoUserFound),
+        Err(_) => Err(ModelError::SqlError),
+    }
+}
+
+// Never errors - anything that goes wrong is what the report is for. `prepared` is
+// whether startup has finished migrating and syncing the cohorts
+pub async fn readiness(
+    store: &dyn Store,
+    prepared: bool,
+    timeout: std::time::Duration,
+) -> Readiness {
+    let started = std::time::Instant::now();
+    let error = match tokio::time::timeout(timeout, store.ping()).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            error!("Readiness check couldn't reach the db: {:?}", e);
+            Some(String::from("query failed"))
+        }
+        Err(_) => {
+            error!("Readiness check timed out after {:?}", timeout);
+            Some(String::from("timed out"))
+        }
+    };
+    let latency_ms = error
+        .is_none()
+        .then(|| started.elapsed().as_millis() as u64);
+    // Reachable isn't enough if we started degraded and haven't migrated yet
+    let (error, migration_version) = match error {
+        Some(error) => (Some(error), None),
+        None => match tokio::time::timeout(timeout, store.migration_version()).await {
+            Ok(Ok(version)) => (None, version),
+            Ok(Err(e)) => {
+                error!(
+                    "Readiness check couldn't find the migration version: {:?}",
+                    e
+                );
+                (Some(String::from("not migrated")), None)
+            }
+            Err(_) => (Some(String::from("timed out")), None),
+        },
+    };
+    let error = match error {
+        None if !prepared => Some(String::from("not prepared yet")),
+        error => error,
+    };
+    let pool = store.pool_stats();
+
+    Readiness {
+        ready: error.is_none(),
+        database: DatabaseHealth {
+            backend: store.backend().to_string(),
+            reachable: latency_ms.is_some(),
+            latency_ms,
+            error,
+            pool_size: pool.map(|pool| pool.size),
+            idle_connections: pool.map(|pool| pool.idle),
+            migration_version,
+        },
+        build: BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: option_env!("GIT_SHA").map(String::from),
+        },
     }
 }
 
+// What check-config uses to make sure a challenge config is actually gradeable - builds
+// one for a made up applicant and checks there's something to answer
+pub fn challenge_has_answers(challenge: &ChallengeSettings) -> bool {
+    let (challenge, solution) = generate_challenge("000000000", challenge.size, mandatory_cases());
+    !challenge.is_empty() && !solution.is_empty()
+}
+
+// Every challenge gets these on top of the random ones
+pub(super) fn mandatory_cases() -> Vec<String> {
+    vec![
+        String::from(""),
+        Color::Red.to_string(),
+        Color::Orange.to_string(),
+        Color::Yellow.to_string(),
+        Color::Green.to_string(),
+        Color::Blue.to_string(),
+        Color::Violet.to_string(),
+    ]
+}
+
 #[derive(EnumIter, Debug)]
 enum EditType {
     Insertion,
@@ -110,7 +350,7 @@ enum EditType {
     Substitution,
 }
 
-fn generate_challenge(
+pub(super) fn generate_challenge(
     nuid: &str,
     n_random: usize,
     mandatory_cases: Vec<String>,
@@ -215,10 +455,33 @@ fn one_edit_away(str: &str) -> bool {
 #[cfg(test)]
 mod tests {
 
+    use chrono::{Duration as ChronoDuration, Utc};
+    use std::time::Duration;
+
     use super::generate_challenge;
     use super::one_edit_away;
+    use super::time_to_completion;
     use super::Color;
 
+    #[test]
+    fn test_time_to_completion_takes_off_extra_time() {
+        let registered = Utc::now();
+        let submitted = registered + ChronoDuration::hours(2);
+        assert_eq!(
+            time_to_completion(&registered, &submitted, 0),
+            Duration::from_secs(7200)
+        );
+        assert_eq!(
+            time_to_completion(&registered, &submitted, 1800),
+            Duration::from_secs(5400)
+        );
+        // More extra time than they used doesn't go negative
+        assert_eq!(
+            time_to_completion(&registered, &submitted, 10800),
+            Duration::ZERO
+        );
+    }
+
     #[test]
     fn test_generate_challenge() {
         let mandatory_cases: Vec<String> = vec![
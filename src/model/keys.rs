@@ -0,0 +1,73 @@
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+use serde_json::json;
+
+use super::admin::{audit_failure, entry};
+use crate::{
+    db::{Store, StoreError},
+    endpoints::errors::ModelError,
+};
+
+const KEY_LENGTH: usize = 40;
+
+// Only the hash ever gets stored, so the key is shown once when it's made and that's it
+fn hash_key(pepper: &str, key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(pepper.as_bytes());
+    hasher.update(key.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub async fn create_reviewer_key(
+    store: &dyn Store,
+    actor: &str,
+    name: &str,
+    pepper: &str,
+) -> Result<String, ModelError> {
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(KEY_LENGTH)
+        .map(char::from)
+        .collect();
+
+    let detail = json!({ "name": name });
+    let audit = entry(actor, "create-key", detail.clone());
+    let result = match store
+        .create_api_key(name, &hash_key(pepper, &key), Utc::now(), &audit)
+        .await
+    {
+        Ok(()) => Ok(key),
+        // The hash is unique too, but two random keys colliding isn't worth a message
+        Err(StoreError::Duplicate) => Err(ModelError::DuplicateKey),
+        Err(_) => Err(ModelError::SqlError),
+    };
+    audit_failure(store, actor, "create-key", detail, result).await
+}
+
+pub async fn revoke_reviewer_key(
+    store: &dyn Store,
+    actor: &str,
+    name: &str,
+) -> Result<(), ModelError> {
+    let detail = json!({ "name": name });
+    let audit = entry(actor, "revoke-key", detail.clone());
+    let result = match store.revoke_api_key(name, Utc::now(), &audit).await {
+        Ok(0) => Err(ModelError::NoKeyFound),
+        Ok(_) => Ok(()),
+        Err(_) => Err(ModelError::SqlError),
+    };
+    audit_failure(store, actor, "revoke-key", detail, result).await
+}
+
+pub async fn verify_reviewer_key(
+    store: &dyn Store,
+    pepper: &str,
+    key: &str,
+) -> Result<bool, ModelError> {
+    match store.api_key_exists(&hash_key(pepper, key)).await {
+        Ok(exists) => Ok(exists),
+        Err(_) => Err(ModelError::SqlError),
+    }
+}
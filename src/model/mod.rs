@@ -1,5 +1,8 @@
+pub mod admin;
 pub mod engine;
+pub mod keys;
 pub mod types;
 pub use engine::{
-    check_solution, get_applicants, register_user, retreive_challenge, retreive_token,
+    check_solution, get_applicants, get_status, grant_extension, readiness, register_user,
+    retreive_challenge, retreive_token, sync_cohorts,
 };
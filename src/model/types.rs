@@ -1,16 +1,114 @@
+use chrono::{DateTime, Utc};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use std::time::Duration;
 use strum::EnumIter;
 
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Applicant {
+    // With any extra time they were granted taken off
+    #[schema(value_type = DurationSchema)]
     pub time_to_completion: Duration,
     pub ok: bool,
+    // Whether this submission came in after the cohort's deadline, extension included
+    pub late: bool,
+    // Any accommodations they were granted
+    #[schema(value_type = DurationSchema)]
+    pub extra_time: Duration,
+    pub extra_attempts: i64,
     pub name: String,
     pub nuid: String,
+    pub cohort: String,
+}
+
+// What the admin cli lists - covers people who haven't submitted yet too
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApplicantSummary {
+    pub nuid: String,
+    pub name: String,
+    pub cohort: String,
+    pub registration_time: DateTime<Utc>,
+    pub attempts: i64,
+    pub passed: bool,
+    pub late: bool,
+    pub time_to_completion: Option<Duration>,
+    pub extra_time: Duration,
+    pub extra_attempts: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ApplicantStatus {
+    pub registration_time: DateTime<Utc>,
+    pub attempts: i64,
+    pub passed: bool,
+    // When the first correct submission came in
+    pub first_success: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<DurationSchema>)]
+    pub time_to_completion: Option<Duration>,
+    // Both of these are null when there's no limit configured, and both include
+    // any extension the applicant was granted
+    pub remaining_attempts: Option<i64>,
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+// What /health/ready reports. ready is false whenever the db didn't answer in time, or
+// startup hasn't finished migrating and syncing the cohorts
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub database: DatabaseHealth,
+    pub build: BuildInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct DatabaseHealth {
+    pub backend: String,
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    // Kept vague since this route is public, the details go to the logs
+    pub error: Option<String>,
+    // Both null for the in-memory store
+    pub pool_size: Option<u32>,
+    pub idle_connections: Option<u32>,
+    pub migration_version: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BuildInfo {
+    pub version: String,
+    // Set with GIT_SHA at build time
+    pub git_sha: Option<String>,
+}
+
+// What /admin/log_filter reports. reverts_at is null when the base filter is in place
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct LogFilterStatus {
+    pub base: String,
+    pub current: String,
+    pub reverts_at: Option<DateTime<Utc>>,
+}
+
+// The applicant's running totals after an extension is granted
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Extension {
+    pub nuid: String,
+    pub cohort: String,
+    #[schema(value_type = DurationSchema)]
+    pub extra_time: Duration,
+    pub extra_attempts: i64,
+    // The applicant's own deadline now, null if their cohort doesn't have one
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+// serde writes a std::time::Duration out as its seconds and nanoseconds
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct DurationSchema {
+    secs: u64,
+    nanos: u32,
 }
 
 #[derive(EnumIter, Debug)]
@@ -0,0 +1,112 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::model::types::LogFilterStatus;
+
+static LOG_FILTER: OnceLock<LogFilter> = OnceLock::new();
+
+// Whatever filter the process started with stays the base. Changes made through
+// /admin/log_filter get added on top of it, and always go back to it when they run out
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    base: String,
+    state: Mutex<State>,
+}
+
+struct State {
+    current: String,
+    reverts_at: Option<DateTime<Utc>>,
+    // Bumped on every change, so the revert timer of an older change can't undo a
+    // newer one
+    change: u64,
+}
+
+pub(super) fn install(handle: reload::Handle<EnvFilter, Registry>, base: String) {
+    let _ = LOG_FILTER.set(LogFilter {
+        handle,
+        state: Mutex::new(State {
+            current: base.clone(),
+            reverts_at: None,
+            change: 0,
+        }),
+        base,
+    });
+}
+
+// None until telemetry::init has run, the tests never get one
+pub fn get() -> Option<&'static LogFilter> {
+    LOG_FILTER.get()
+}
+
+impl LogFilter {
+    pub fn status(&self) -> LogFilterStatus {
+        let state = self.state.lock().unwrap();
+        LogFilterStatus {
+            base: self.base.clone(),
+            current: state.current.clone(),
+            reverts_at: state.reverts_at,
+        }
+    }
+
+    // `directives` are RUST_LOG style, like generate_tech_app::db::transactions=debug
+    pub fn apply(
+        &'static self,
+        directives: &[String],
+        duration: Duration,
+    ) -> Result<LogFilterStatus, String> {
+        if directives.is_empty() {
+            return Err(String::from("no directives given"));
+        }
+        let current = std::iter::once(&self.base)
+            .chain(directives)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(",");
+        let filter = EnvFilter::try_new(&current).map_err(|e| e.to_string())?;
+
+        let change = {
+            let mut state = self.state.lock().unwrap();
+            self.handle.reload(filter).map_err(|e| e.to_string())?;
+            state.change += 1;
+            state.current = current;
+            state.reverts_at =
+                Some(Utc::now() + chrono::Duration::seconds(duration.as_secs() as i64));
+            state.change
+        };
+        warn!(
+            "Log filter changed to {} for {:?}",
+            self.status().current,
+            duration
+        );
+
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            self.revert(Some(change));
+        });
+        Ok(self.status())
+    }
+
+    pub fn reset(&self) -> LogFilterStatus {
+        self.revert(None);
+        self.status()
+    }
+
+    fn revert(&self, change: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        if state.reverts_at.is_none() || change.is_some_and(|change| change != state.change) {
+            return;
+        }
+        let filter = EnvFilter::try_new(&self.base).expect("the base filter parsed at startup");
+        match self.handle.reload(filter) {
+            Ok(()) => {
+                state.current = self.base.clone();
+                state.reverts_at = None;
+                warn!("Log filter reverted to {}", self.base);
+            }
+            Err(e) => error!("Couldn't revert the log filter: {}", e),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, EnvFilter};
+
+use crate::config::{LogFormat, LoggingSettings, TraceExporter, TracingSettings};
+
+pub mod filter;
+pub mod redact;
+
+// Sets up the process wide subscriber. `filter` is --log-level, which wins over
+// RUST_LOG, which wins over logging.filter. Anything logged through the `log` crate
+// (sqlx, warp, hyper) ends up in here too. The filter only decides what gets logged,
+// traces always get our own spans
+pub fn init(
+    logging: &LoggingSettings,
+    tracing: Option<&TracingSettings>,
+    filter: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let filter = match filter {
+        Some(filter) => filter.to_string(),
+        None => std::env::var("RUST_LOG").unwrap_or_else(|_| logging.filter.clone()),
+    };
+    // Swappable so /admin/log_filter can change it while we're running
+    let (reloadable, handle) = reload::Layer::new(EnvFilter::try_new(&filter)?);
+    filter::install(handle, filter);
+
+    redact::init(&logging.pii);
+
+    let fields = &logging.fields;
+    let logs = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_target(fields.target)
+        .with_file(fields.file)
+        .with_line_number(fields.line)
+        .with_thread_ids(fields.thread)
+        .with_thread_names(fields.thread);
+    // The request id lives on the outermost span, so json lines carry the whole list
+    // rather than just whichever span is innermost
+    let logs = match logging.format {
+        LogFormat::Pretty => logs.boxed(),
+        LogFormat::Json => logs
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let traces = match tracing {
+        Some(settings) if settings.exporter != TraceExporter::None => {
+            global::set_text_map_propagator(TraceContextPropagator::new());
+            // Export failures (collector down etc.) would otherwise go straight to stderr
+            let _ = global::set_error_handler(|e| warn!("Trace export failed: {}", e));
+            Some(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer(settings)?)
+                    .with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::INFO)),
+            )
+        }
+        _ => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(logs.with_filter(reloadable))
+        .with(traces)
+        .try_init()?;
+    Ok(())
+}
+
+fn tracer(settings: &TracingSettings) -> Result<Tracer, Box<dyn Error>> {
+    let config = trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sample_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]));
+
+    let tracer = match settings.exporter {
+        TraceExporter::Otlp => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(&settings.endpoint),
+            )
+            .with_trace_config(config)
+            .install_batch(runtime::Tokio)?,
+        TraceExporter::File => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&settings.path)?;
+            let provider = TracerProvider::builder()
+                .with_config(config)
+                .with_batch_exporter(
+                    opentelemetry_stdout::SpanExporter::builder()
+                        .with_writer(file)
+                        .build(),
+                    runtime::Tokio,
+                )
+                .build();
+            let tracer = provider.tracer(settings.service_name.clone());
+            global::set_tracer_provider(provider);
+            tracer
+        }
+        TraceExporter::None => unreachable!("no tracer without an exporter"),
+    };
+    Ok(tracer)
+}
+
+// Flushes whatever spans are still batched up. Blocks, so it gets a thread of its own
+pub async fn shutdown() {
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
@@ -0,0 +1,148 @@
+use std::fmt::Debug;
+use std::sync::OnceLock;
+
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::{NuidRedaction, PiiSettings};
+use crate::endpoints::metrics::route_label;
+
+// Set once by telemetry::init. Anything that logs before then (or without it, like the
+// tests) gets the defaults, so nothing slips out unredacted by accident
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+pub fn init(settings: &PiiSettings) {
+    let _ = POLICY.set(Policy::new(settings));
+}
+
+fn policy() -> &'static Policy {
+    POLICY.get_or_init(|| Policy::new(&PiiSettings::default()))
+}
+
+pub fn nuid(nuid: &str) -> String {
+    policy().identifier(nuid)
+}
+
+pub fn nuids(nuids: &[String]) -> String {
+    let nuids: Vec<String> = nuids.iter().map(|nuid| policy().identifier(nuid)).collect();
+    policy().payload(&nuids)
+}
+
+// Tokens get the same treatment as NUIDs - they're as good as a password for /submit
+pub fn token(token: &Uuid) -> String {
+    policy().identifier(&token.to_string())
+}
+
+// Meant for an optional field, so a dropped name doesn't show up at all
+pub fn name(name: &str) -> Option<&str> {
+    policy().unredacted.then_some(name)
+}
+
+pub fn payload(payload: &impl Debug) -> String {
+    policy().payload(payload)
+}
+
+// NUIDs and tokens ride along in paths, so only the route they hit gets logged
+pub fn path(path: &str) -> &str {
+    if policy().unredacted {
+        path
+    } else {
+        route_label(path)
+    }
+}
+
+struct Policy {
+    nuids: NuidRedaction,
+    payload_limit: usize,
+    hash_key: String,
+    unredacted: bool,
+}
+
+impl Policy {
+    fn new(settings: &PiiSettings) -> Self {
+        let hash_key = match &settings.hash_key {
+            Some(key) => key.expose().clone(),
+            None => rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect(),
+        };
+        Self {
+            nuids: settings.nuids,
+            payload_limit: settings.payload_limit,
+            hash_key,
+            unredacted: settings.unredacted,
+        }
+    }
+
+    fn identifier(&self, value: &str) -> String {
+        if self.unredacted {
+            return value.to_string();
+        }
+        match self.nuids {
+            NuidRedaction::Hash => {
+                let mut hasher = Sha256::new();
+                hasher.update(self.hash_key.as_bytes());
+                hasher.update(value.as_bytes());
+                format!("#{}", &hex::encode(hasher.finalize())[..12])
+            }
+            NuidRedaction::Mask => {
+                let len = value.chars().count();
+                value
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| if i + 3 < len { '*' } else { c })
+                    .collect()
+            }
+        }
+    }
+
+    fn payload(&self, payload: &impl Debug) -> String {
+        let payload = format!("{:?}", payload);
+        let len = payload.chars().count();
+        if self.unredacted || len <= self.payload_limit {
+            return payload;
+        }
+        let kept: String = payload.chars().take(self.payload_limit).collect();
+        format!("{}... ({} more chars)", kept, len - self.payload_limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Secret;
+
+    fn policy(nuids: NuidRedaction, unredacted: bool) -> Policy {
+        Policy::new(&PiiSettings {
+            nuids,
+            payload_limit: 10,
+            hash_key: Some(Secret::new(String::from("key"))),
+            unredacted,
+        })
+    }
+
+    #[test]
+    fn test_redaction() {
+        let hash = policy(NuidRedaction::Hash, false);
+        assert_eq!(hash.identifier("001234567"), hash.identifier("001234567"));
+        assert_ne!(hash.identifier("001234567"), hash.identifier("001234568"));
+        assert!(!hash.identifier("001234567").contains("1234567"));
+        assert_eq!(
+            hash.payload(&"a long challenge string"),
+            "\"a long ch... (15 more chars)"
+        );
+
+        let mask = policy(NuidRedaction::Mask, false);
+        assert_eq!(mask.identifier("001234567"), "******567");
+
+        let unredacted = policy(NuidRedaction::Hash, true);
+        assert_eq!(unredacted.identifier("001234567"), "001234567");
+        assert_eq!(
+            unredacted.payload(&"a long challenge string"),
+            "\"a long challenge string\""
+        );
+    }
+}